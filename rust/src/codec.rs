@@ -0,0 +1,279 @@
+//! Tokio codec for streaming V-Frames over a reliable byte stream (TCP, QUIC, ...).
+//!
+//! The example binaries only ever read one frame per `UdpSocket::recv_from`, which
+//! caps a frame at one datagram and assumes no buffering is needed. [`VFrameCodec`]
+//! lets callers do `Framed::new(stream, VFrameCodec::default())` to get a
+//! `Stream`/`Sink` of [`VFrame`]s over any `AsyncRead + AsyncWrite`, including byte
+//! streams where a frame can span multiple reads.
+
+use std::convert::TryFrom;
+use std::io::Cursor;
+
+use anyhow::{anyhow, Context};
+use byteorder::{LittleEndian, ReadBytesExt};
+use bytes::BytesMut;
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::{Flags, MsgType, Network, VFrame};
+
+/// Mandatory header bytes before the slice-length table: magic + version + mtype +
+/// flags + stream_id + frame_seq + num_slices.
+const MIN_HEADER_LEN: usize = 4 + 1 + 1 + 2 + 4 + 8 + 8;
+
+#[derive(Default)]
+pub struct VFrameCodec;
+
+impl Decoder for VFrameCodec {
+    type Item = VFrame;
+    type Error = anyhow::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> anyhow::Result<Option<VFrame>> {
+        loop {
+            let total_len = match peek_frame_len(src) {
+                Ok(Some(len)) => len,
+                Ok(None) => return Ok(None),
+                Err(err) => {
+                    // The bytes at the front don't parse as a frame (foreign magic or a
+                    // malformed header) — look past them for the next plausible frame
+                    // boundary and resync instead of tearing down the whole connection.
+                    match VFrame::find_next(&src[1..]) {
+                        Some(skip) => {
+                            let _ = src.split_to(1 + skip);
+                            continue;
+                        }
+                        None => return Err(err),
+                    }
+                }
+            };
+
+            let frame = src.split_to(total_len);
+            return VFrame::decode(&frame).map(Some);
+        }
+    }
+}
+
+impl Encoder<VFrame> for VFrameCodec {
+    type Error = anyhow::Error;
+
+    fn encode(&mut self, item: VFrame, dst: &mut BytesMut) -> anyhow::Result<()> {
+        let bytes = item.encode()?;
+        dst.extend_from_slice(&bytes);
+        Ok(())
+    }
+}
+
+/// Compute the total on-wire length of the frame sitting at the front of `buf`,
+/// without consuming it. Returns `Ok(None)` if `buf` doesn't yet hold the whole frame,
+/// so the caller can wait for more bytes instead of treating a partial frame as
+/// garbage. Returns the same errors `VFrame::decode` would on a malformed header.
+pub(crate) fn peek_frame_len(buf: &[u8]) -> anyhow::Result<Option<usize>> {
+    if buf.len() < MIN_HEADER_LEN {
+        return Ok(None);
+    }
+
+    let magic: [u8; 4] = buf[..4].try_into().unwrap();
+    Network::from_magic(magic)
+        .ok_or_else(|| anyhow::Error::new(crate::ForeignMagicError { found: magic }))?;
+
+    let mut cursor = Cursor::new(&buf[4..]);
+    let _version = cursor.read_u8().context("Failed to read version")?;
+    let mtype = cursor.read_u8().context("Failed to read message type")?;
+    MsgType::from_u8(mtype).ok_or_else(|| anyhow!("Invalid message type"))?;
+    let flags = Flags::from_bits(
+        cursor
+            .read_u16::<LittleEndian>()
+            .context("Failed to read flags")?,
+    )
+    .ok_or_else(|| anyhow!("Invalid flags"))?;
+    let _stream_id = cursor
+        .read_u32::<LittleEndian>()
+        .context("Failed to read stream_id")?;
+    let _frame_seq = cursor
+        .read_u64::<LittleEndian>()
+        .context("Failed to read frame_seq")?;
+    let num_slices = cursor
+        .read_u64::<LittleEndian>()
+        .context("Failed to read num_slices")?;
+
+    let slice_len_table_bytes = if num_slices == 1 {
+        4
+    } else {
+        usize::try_from(num_slices)
+            .context("num_slices exceeds usize")?
+            .checked_mul(4)
+            .context("slice_len table size overflow")?
+    };
+
+    let mut offset = 4 + cursor.position() as usize;
+    // space_hash32 (4B) + modality (1B) + fragment_idx (4B) + fragment_total (4B)
+    // follow the slice_len table.
+    if buf.len() < offset + slice_len_table_bytes + 4 + 1 + 4 + 4 {
+        return Ok(None);
+    }
+
+    let mut table_cursor = Cursor::new(&buf[offset..offset + slice_len_table_bytes]);
+    let slice_len = if num_slices == 1 {
+        vec![table_cursor
+            .read_u32::<LittleEndian>()
+            .context("Failed to read slice_len")?]
+    } else {
+        let mut lens = Vec::with_capacity(num_slices as usize);
+        for _ in 0..num_slices {
+            lens.push(
+                table_cursor
+                    .read_u32::<LittleEndian>()
+                    .context("Failed to read slice_len")?,
+            );
+        }
+        lens
+    };
+
+    offset += slice_len_table_bytes + 4 + 1 + 4 + 4;
+
+    if flags.contains(Flags::XCHACHA) {
+        // The slice region is a single opaque `ciphertext || 16B tag` blob, not
+        // per-slice `dtype/shape/payload` records — `slice_len` only ever tracks
+        // plaintext payload bytes, so it can't size this region. `parse_header` writes
+        // the sealed region's true length right after the rest of the plaintext
+        // header; read that instead of walking slice structure that isn't there.
+        if buf.len() < offset + 4 {
+            return Ok(None);
+        }
+        let mut sealed_len_cursor = Cursor::new(&buf[offset..offset + 4]);
+        let sealed_len = sealed_len_cursor
+            .read_u32::<LittleEndian>()
+            .context("Failed to read sealed_len")? as usize;
+        offset += 4 + sealed_len;
+    } else {
+        for slice_idx in 0..num_slices {
+            if buf.len() < offset + 2 {
+                return Ok(None);
+            }
+            let shape_len = buf[offset + 1] as usize;
+            let shape_bytes = shape_len * 4;
+
+            if buf.len() < offset + 2 + shape_bytes {
+                return Ok(None);
+            }
+            offset += 2 + shape_bytes;
+
+            let declared_len = if slice_len.len() == 1 {
+                slice_len[0] as usize
+            } else {
+                let idx = usize::try_from(slice_idx).context("num_slices exceeds usize")?;
+                slice_len[idx] as usize
+            };
+            offset += declared_len;
+        }
+    }
+
+    let total_len = offset + 4; // trailing CRC32
+    if buf.len() < total_len {
+        return Ok(None);
+    }
+
+    Ok(Some(total_len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DType, Flags, Modality, Network, SliceMeta, VFrameHeader};
+
+    fn sample_frame() -> VFrame {
+        VFrame {
+            hdr: VFrameHeader {
+                network: Network::Main,
+                version: 1,
+                mtype: MsgType::Think,
+                flags: Flags::empty(),
+                stream_id: 0x1234,
+                frame_seq: 1,
+                num_slices: 1,
+                slice_len: vec![4],
+                space_hash32: 2451163210,
+                modality: Modality::Text,
+                fragment_idx: 0,
+                fragment_total: 1,
+            },
+            slices: vec![(
+                SliceMeta {
+                    dtype: DType::I8,
+                    shape: vec![4],
+                },
+                vec![1, 2, 3, 4],
+            )],
+            crc32: 0,
+        }
+    }
+
+    #[test]
+    fn test_decoder_waits_for_full_frame() {
+        let mut codec = VFrameCodec;
+        let mut dst = BytesMut::new();
+        codec.encode(sample_frame(), &mut dst).unwrap();
+
+        let mut partial = BytesMut::from(&dst[..dst.len() - 1]);
+        assert!(codec.decode(&mut partial).unwrap().is_none());
+        // No bytes should be consumed while waiting for the rest of the frame.
+        assert_eq!(partial.len(), dst.len() - 1);
+    }
+
+    #[test]
+    fn test_decoder_yields_frame_once_complete() {
+        let mut codec = VFrameCodec;
+        let mut dst = BytesMut::new();
+        codec.encode(sample_frame(), &mut dst).unwrap();
+        dst.extend_from_slice(b"trailing-bytes-of-next-frame");
+
+        let frame = codec.decode(&mut dst).unwrap().unwrap();
+        assert_eq!(frame.hdr.stream_id, 0x1234);
+        assert_eq!(dst, b"trailing-bytes-of-next-frame".as_ref());
+    }
+
+    #[test]
+    fn test_decoder_resyncs_past_corrupt_bytes() {
+        let mut codec = VFrameCodec;
+        let mut dst = BytesMut::new();
+        dst.extend_from_slice(b"garbage-before-the-real-frame");
+        codec.encode(sample_frame(), &mut dst).unwrap();
+
+        let frame = codec
+            .decode(&mut dst)
+            .unwrap()
+            .expect("decoder should resync past the leading garbage");
+        assert_eq!(frame.hdr.stream_id, 0x1234);
+        assert!(dst.is_empty());
+    }
+
+    #[test]
+    fn test_decoder_errors_when_no_magic_is_found() {
+        let mut codec = VFrameCodec;
+        let mut dst = BytesMut::from(&b"not-a-v-frame-at-all-just-noise"[..]);
+
+        assert!(codec.decode(&mut dst).is_err());
+    }
+
+    #[test]
+    fn test_peek_frame_len_sizes_xchacha_frame_without_decrypting() {
+        let key = [3u8; 32];
+        let mut frame = sample_frame();
+        frame.hdr.flags = Flags::XCHACHA;
+
+        let encoded = frame
+            .encode_encrypted(crate::DEFAULT_COMPRESSION_THRESHOLD, &key)
+            .unwrap();
+
+        let mut buf = encoded.clone();
+        buf.extend_from_slice(b"trailing-bytes-of-next-frame");
+
+        // Before the fix this misread the ciphertext as per-slice struct bytes and
+        // returned `Ok(None)` forever, stalling the decoder even once the whole frame
+        // had arrived.
+        let total_len = peek_frame_len(&buf).unwrap().expect("frame is fully buffered");
+        assert_eq!(total_len, encoded.len());
+
+        let decoded = VFrame::decode_encrypted(&buf[..total_len], &key).unwrap();
+        assert_eq!(decoded.hdr.stream_id, frame.hdr.stream_id);
+    }
+}
@@ -1,7 +1,65 @@
+use anyhow::ensure;
 use chacha20poly1305::{
-    aead::{Aead, KeyInit},
+    aead::{Aead, KeyInit, Payload},
     XChaCha20Poly1305, XNonce,
 };
+use hkdf::Hkdf;
+use sha2::Sha256;
+use x25519_dalek::{EphemeralSecret, PublicKey};
+
+/// Plaintext chunk size used by [`ChaChaPolyWriter`]/[`ChaChaPolyReader`] to stream an
+/// oversized payload (e.g. a `Think` hidden-state or KV-cache slice) past the 64 KiB
+/// V-Frame cap.
+pub const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
+/// A 32-byte XChaCha20-Poly1305 session key derived from an X25519 shared secret.
+#[derive(Clone)]
+pub struct SessionKey(pub [u8; 32]);
+
+/// Ephemeral X25519 key-agreement handshake run during the `Sync`/capability exchange.
+///
+/// Each side calls [`Handshake::initiate`] to generate a fresh keypair and advertises
+/// the returned public key in the `Sync` frame's `x25519_pub` field. On receiving the
+/// peer's public key, [`Handshake::complete`] performs the Diffie-Hellman exchange and
+/// derives the session key used to encrypt/decrypt the rest of the stream.
+pub struct Handshake {
+    secret: EphemeralSecret,
+}
+
+impl Handshake {
+    /// Generate an ephemeral keypair. The public key half is sent to the peer; the
+    /// `Handshake` itself is kept to complete the exchange once the peer's public key
+    /// arrives.
+    pub fn initiate() -> (Handshake, PublicKey) {
+        let secret = EphemeralSecret::random_from_rng(rand::thread_rng());
+        let public = PublicKey::from(&secret);
+        (Handshake { secret }, public)
+    }
+
+    /// Complete the handshake given the peer's public key, deriving the session key
+    /// via HKDF-SHA256 over the X25519 shared secret. The agreed `embedding_space_id`
+    /// and `space_hash32` from the capability exchange are bound in as HKDF salt so a
+    /// session key is only reused by peers that negotiated the same embedding space.
+    pub fn complete(
+        self,
+        peer_pub: [u8; 32],
+        embedding_space_id: &str,
+        space_hash32: u32,
+    ) -> anyhow::Result<SessionKey> {
+        let shared = self.secret.diffie_hellman(&PublicKey::from(peer_pub));
+
+        let mut salt = Vec::with_capacity(embedding_space_id.len() + 4);
+        salt.extend_from_slice(embedding_space_id.as_bytes());
+        salt.extend_from_slice(&space_hash32.to_le_bytes());
+
+        let hk = Hkdf::<Sha256>::new(Some(&salt), shared.as_bytes());
+        let mut key = [0u8; 32];
+        hk.expand(b"resonant-protocol session key", &mut key)
+            .map_err(|e| anyhow::anyhow!("HKDF expand failed: {}", e))?;
+
+        Ok(SessionKey(key))
+    }
+}
 
 /// Encrypt data using XChaCha20-Poly1305
 pub fn seal_xchacha(key: &[u8; 32], nonce: &[u8; 24], plaintext: &[u8]) -> anyhow::Result<Vec<u8>> {
@@ -25,6 +83,178 @@ pub fn open_xchacha(
         .map_err(|e| anyhow::anyhow!("Decryption failed: {}", e))
 }
 
+/// A whole-frame XChaCha20-Poly1305 tag failed to verify. CRC32 only catches accidental
+/// corruption, not forgery, so this is its own type rather than a generic decode error.
+#[derive(Debug)]
+pub struct FrameAuthError;
+
+impl std::fmt::Display for FrameAuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "frame failed XChaCha20-Poly1305 authentication")
+    }
+}
+
+impl std::error::Error for FrameAuthError {}
+
+/// Encrypt data with XChaCha20-Poly1305, binding `aad` into the authentication tag
+/// without including it in the ciphertext. Used to seal a V-Frame's slice region while
+/// binding the frame header into the tag.
+pub fn seal_xchacha_aad(
+    key: &[u8; 32],
+    nonce: &[u8; 24],
+    plaintext: &[u8],
+    aad: &[u8],
+) -> anyhow::Result<Vec<u8>> {
+    let cipher = XChaCha20Poly1305::new(key.into());
+    cipher
+        .encrypt(
+            XNonce::from_slice(nonce),
+            Payload {
+                msg: plaintext,
+                aad,
+            },
+        )
+        .map_err(|e| anyhow::anyhow!("Encryption failed: {}", e))
+}
+
+/// Decrypt data sealed by [`seal_xchacha_aad`]. Returns [`FrameAuthError`] (rather than
+/// a generic error) when the tag fails to verify.
+pub fn open_xchacha_aad(
+    key: &[u8; 32],
+    nonce: &[u8; 24],
+    ciphertext: &[u8],
+    aad: &[u8],
+) -> anyhow::Result<Vec<u8>> {
+    let cipher = XChaCha20Poly1305::new(key.into());
+    cipher
+        .decrypt(
+            XNonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad,
+            },
+        )
+        .map_err(|_| anyhow::Error::new(FrameAuthError))
+}
+
+/// One chunk of a [`ChaChaPolyReader`] stream failed Poly1305 authentication. Carries
+/// `chunk_idx` so a caller can report which chunk of a truncated or forged stream was
+/// at fault.
+#[derive(Debug)]
+pub struct StreamAuthError {
+    pub chunk_idx: u64,
+}
+
+impl std::fmt::Display for StreamAuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "chunk {} failed Poly1305 authentication", self.chunk_idx)
+    }
+}
+
+impl std::error::Error for StreamAuthError {}
+
+/// Derive the per-chunk nonce from `(stream_id, frame_seq, chunk_idx)` so no two chunks
+/// in a stream ever reuse a nonce: `stream_id` (4B) || `frame_seq` (8B) || `chunk_idx`
+/// (8B, little-endian, zero-padded to fill the remaining 4 bytes of the 24-byte nonce).
+fn chunk_nonce(stream_id: u32, frame_seq: u64, chunk_idx: u64) -> [u8; 24] {
+    let mut nonce = [0u8; 24];
+    nonce[0..4].copy_from_slice(&stream_id.to_le_bytes());
+    nonce[4..12].copy_from_slice(&frame_seq.to_le_bytes());
+    nonce[12..20].copy_from_slice(&chunk_idx.to_le_bytes());
+    nonce
+}
+
+/// Encrypts an oversized payload as a sequence of fixed-size chunks (see
+/// [`STREAM_CHUNK_SIZE`]), each sealed independently so the whole payload never has to
+/// be buffered as a single AEAD call.
+pub struct ChaChaPolyWriter {
+    cipher: XChaCha20Poly1305,
+    stream_id: u32,
+    frame_seq: u64,
+    next_chunk: u64,
+}
+
+impl ChaChaPolyWriter {
+    pub fn new(key: &[u8; 32], stream_id: u32, frame_seq: u64) -> Self {
+        Self {
+            cipher: XChaCha20Poly1305::new(key.into()),
+            stream_id,
+            frame_seq,
+            next_chunk: 0,
+        }
+    }
+
+    /// Seal one plaintext chunk (at most [`STREAM_CHUNK_SIZE`] bytes) as
+    /// `ciphertext || 16-byte Poly1305 tag`, advancing the chunk counter so the next
+    /// call derives a fresh nonce. A chunk shorter than [`STREAM_CHUNK_SIZE`] marks
+    /// end-of-stream on the reader side.
+    pub fn seal_chunk(&mut self, plaintext: &[u8]) -> anyhow::Result<Vec<u8>> {
+        ensure!(
+            plaintext.len() <= STREAM_CHUNK_SIZE,
+            "chunk of {} bytes exceeds STREAM_CHUNK_SIZE ({})",
+            plaintext.len(),
+            STREAM_CHUNK_SIZE
+        );
+
+        let nonce = chunk_nonce(self.stream_id, self.frame_seq, self.next_chunk);
+        let sealed = self
+            .cipher
+            .encrypt(XNonce::from_slice(&nonce), plaintext)
+            .map_err(|e| anyhow::anyhow!("Encryption failed: {}", e))?;
+        self.next_chunk += 1;
+        Ok(sealed)
+    }
+}
+
+/// Decrypts a sequence of chunks produced by [`ChaChaPolyWriter`], authenticating each
+/// one before returning its plaintext.
+///
+/// Tag verification goes through the `chacha20poly1305` crate's AEAD implementation,
+/// which compares tags in constant time, so the accept/reject branch here never leaks
+/// secret data through timing.
+pub struct ChaChaPolyReader {
+    cipher: XChaCha20Poly1305,
+    stream_id: u32,
+    frame_seq: u64,
+    next_chunk: u64,
+}
+
+impl ChaChaPolyReader {
+    pub fn new(key: &[u8; 32], stream_id: u32, frame_seq: u64) -> Self {
+        Self {
+            cipher: XChaCha20Poly1305::new(key.into()),
+            stream_id,
+            frame_seq,
+            next_chunk: 0,
+        }
+    }
+
+    /// Open one sealed chunk. Returns a [`StreamAuthError`] (downcastable from the
+    /// returned `anyhow::Error`) if the Poly1305 tag fails to verify, so a truncated or
+    /// forged stream is rejected rather than silently decoded.
+    pub fn open_chunk(&mut self, sealed: &[u8]) -> anyhow::Result<Vec<u8>> {
+        ensure!(
+            sealed.len() >= 16,
+            "chunk of {} bytes is too short to contain a Poly1305 tag",
+            sealed.len()
+        );
+
+        let nonce = chunk_nonce(self.stream_id, self.frame_seq, self.next_chunk);
+        let chunk_idx = self.next_chunk;
+        let plaintext = self
+            .cipher
+            .decrypt(XNonce::from_slice(&nonce), sealed)
+            .map_err(|_| anyhow::Error::new(StreamAuthError { chunk_idx }))?;
+        self.next_chunk += 1;
+        Ok(plaintext)
+    }
+
+    /// Whether a chunk of this plaintext length marks the end of the stream.
+    pub fn is_final_chunk(plaintext_len: usize) -> bool {
+        plaintext_len < STREAM_CHUNK_SIZE
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -40,4 +270,50 @@ mod tests {
 
         assert_eq!(plaintext, decrypted.as_slice());
     }
+
+    #[test]
+    fn test_handshake_agrees_on_session_key() {
+        let (initiator, initiator_pub) = Handshake::initiate();
+        let (responder, responder_pub) = Handshake::initiate();
+
+        let initiator_key = initiator
+            .complete(responder_pub.to_bytes(), "universal-llm-v3", 2451163210)
+            .unwrap();
+        let responder_key = responder
+            .complete(initiator_pub.to_bytes(), "universal-llm-v3", 2451163210)
+            .unwrap();
+
+        assert_eq!(initiator_key.0, responder_key.0);
+    }
+
+    #[test]
+    fn test_stream_cipher_roundtrip_multiple_chunks() {
+        let key = [7u8; 32];
+        let mut writer = ChaChaPolyWriter::new(&key, 0x1234, 9);
+        let mut reader = ChaChaPolyReader::new(&key, 0x1234, 9);
+
+        let full_chunk = vec![0xAAu8; STREAM_CHUNK_SIZE];
+        let final_chunk = vec![0xBBu8; 128];
+
+        for plaintext in [&full_chunk, &full_chunk, &final_chunk] {
+            let sealed = writer.seal_chunk(plaintext).unwrap();
+            let opened = reader.open_chunk(&sealed).unwrap();
+            assert_eq!(&opened, plaintext);
+        }
+        assert!(ChaChaPolyReader::is_final_chunk(final_chunk.len()));
+        assert!(!ChaChaPolyReader::is_final_chunk(full_chunk.len()));
+    }
+
+    #[test]
+    fn test_stream_cipher_rejects_tampered_chunk() {
+        let key = [7u8; 32];
+        let mut writer = ChaChaPolyWriter::new(&key, 0x1234, 9);
+        let mut reader = ChaChaPolyReader::new(&key, 0x1234, 9);
+
+        let mut sealed = writer.seal_chunk(b"hidden-state bytes").unwrap();
+        *sealed.last_mut().unwrap() ^= 0xFF;
+
+        let err = reader.open_chunk(&sealed).unwrap_err();
+        assert!(err.downcast_ref::<StreamAuthError>().is_some());
+    }
 }
@@ -28,6 +28,7 @@ fn main() -> anyhow::Result<()> {
                             MsgType::Critique => handle_critique(&frame)?,
                             MsgType::Cache => println!("   [Cache message]"),
                             MsgType::Ask => println!("   [Ask message]"),
+                            MsgType::Heart => println!("   [Heart keepalive/padding]"),
                         }
 
                         // Verify strong hash if flag is set
@@ -67,14 +68,27 @@ fn print_frame_info(frame: &VFrame, count: usize) {
 fn handle_sync(frame: &VFrame) -> anyhow::Result<()> {
     println!("   [Sync-capability handshake]");
 
-    if !frame.slices.is_empty() {
-        let (_, payload) = &frame.slices[0];
-        match serde_json::from_slice::<serde_json::Value>(payload) {
+    if let Some((_, payload)) = frame.slices.first() {
+        let packet = match resonant_protocol::packet::packet_by_id(
+            frame.hdr.mtype,
+            &mut payload.as_slice(),
+        ) {
+            Some(Ok(resonant_protocol::packet::Packet::SyncPacket(packet))) => packet,
+            Some(Ok(_)) => unreachable!("packet_by_id(MsgType::Sync, ..) only ever returns Packet::SyncPacket"),
+            Some(Err(e)) => {
+                println!("   ⚠️  Could not decode SyncPacket: {}", e);
+                return Ok(());
+            }
+            None => return Ok(()),
+        };
+
+        match serde_json::from_str::<serde_json::Value>(&packet.capability_json) {
             Ok(json) => {
                 println!("   Payload: {}", serde_json::to_string_pretty(&json)?);
 
                 // Send capability response (in real implementation)
-                let response = Capability {
+                let (handshake, our_pub) = resonant_protocol::crypto::Handshake::initiate();
+                let mut response = Capability {
                     method: "capability".to_string(),
                     v: 1,
                     agreed_proto: 1,
@@ -87,7 +101,57 @@ fn handle_sync(frame: &VFrame) -> anyhow::Result<()> {
                         "critique": true,
                         "dtype": ["f16", "i8", "q4", "sparse"]
                     }),
+                    x25519_pub: Some(our_pub.to_bytes()),
+                    compression_threshold: resonant_protocol::DEFAULT_COMPRESSION_THRESHOLD,
+                    supported_protocols: resonant_protocol::SUPPORTED_PROTOCOLS.to_vec(),
                 };
+
+                // Negotiate against the peer's capability so a version/algorithm
+                // mismatch fails fast here instead of surfacing as a decode error on
+                // some later frame.
+                match serde_json::from_value::<Capability>(json) {
+                    Ok(peer) => {
+                        match response.negotiate(&peer) {
+                            Ok(agreed_proto) => {
+                                response.agreed_proto = agreed_proto;
+                                if let Ok(compress) = response.negotiate_compress(&peer) {
+                                    response.compress = vec![compress];
+                                }
+                                if let Ok(crypto) = response.negotiate_crypto(&peer) {
+                                    response.crypto = vec![crypto];
+                                }
+                                println!(
+                                    "   ✅ Negotiated proto v{}, compress={:?}, crypto={:?}",
+                                    response.agreed_proto, response.compress, response.crypto
+                                );
+                            }
+                            Err(e) => println!("   ⚠️  Version negotiation failed: {}", e),
+                        }
+
+                        // Complete the X25519 handshake now that the peer's public key is
+                        // known. This example only reports that the session key was
+                        // derived; it does not use it to decrypt later frames.
+                        match peer.x25519_pub {
+                            Some(peer_pub) => {
+                                match handshake.complete(
+                                    peer_pub,
+                                    &peer.embedding_space_id,
+                                    peer.space_hash32,
+                                ) {
+                                    Ok(_session_key) => {
+                                        println!("   🔑 Derived XChaCha20-Poly1305 session key")
+                                    }
+                                    Err(e) => println!("   ⚠️  Handshake failed: {}", e),
+                                }
+                            }
+                            None => {
+                                println!("   ⚠️  Peer did not advertise an X25519 public key; no session key derived")
+                            }
+                        }
+                    }
+                    Err(e) => println!("   ⚠️  Peer payload is not a Capability: {}", e),
+                }
+
                 println!(
                     "   → Would respond with: {}",
                     serde_json::to_string_pretty(&response)?
@@ -122,17 +186,23 @@ fn handle_think(frame: &VFrame) -> anyhow::Result<()> {
 fn handle_critique(frame: &VFrame) -> anyhow::Result<()> {
     println!("   [Critique message]");
 
-    if frame.slices.len() >= 2 {
-        let (vec_meta, vec_payload) = &frame.slices[0];
-        println!(
-            "   Divergence vector: {:?} shape={:?} size={}",
-            vec_meta.dtype,
-            vec_meta.shape,
-            vec_payload.len()
-        );
+    if let Some((_, payload)) = frame.slices.first() {
+        let packet = match resonant_protocol::packet::packet_by_id(
+            frame.hdr.mtype,
+            &mut payload.as_slice(),
+        ) {
+            Some(Ok(resonant_protocol::packet::Packet::CritiquePacket(packet))) => packet,
+            Some(Ok(_)) => unreachable!("packet_by_id(MsgType::Critique, ..) only ever returns Packet::CritiquePacket"),
+            Some(Err(e)) => {
+                println!("   ⚠️  Could not decode CritiquePacket: {}", e);
+                return Ok(());
+            }
+            None => return Ok(()),
+        };
+
+        println!("   Divergence vector: {} bytes", packet.divergence.len());
 
-        let (_, explain_payload) = &frame.slices[1];
-        if let Ok(json) = serde_json::from_slice::<serde_json::Value>(explain_payload) {
+        if let Ok(json) = serde_json::from_str::<serde_json::Value>(&packet.explanation_json) {
             println!("   Explanation: {}", serde_json::to_string_pretty(&json)?);
         }
     }
@@ -1,4 +1,5 @@
 use rand::Rng;
+use resonant_protocol::packet::{CritiquePacket, Serializable, SyncPacket};
 use resonant_protocol::*;
 use std::net::UdpSocket;
 
@@ -13,14 +14,36 @@ fn main() -> anyhow::Result<()> {
 
     // First, send Sync-capability handshake
     println!("\n1️⃣  Sending Sync-capability handshake...");
-    let capability = serde_json::json!({
-        "method": "ping",
-        "ts": 1730616000u64
-    });
+    // Dropping our half of the handshake is fine here: this example is a one-shot
+    // fire-and-forget sender that never reads a response to complete it against.
+    let (_handshake, our_pub) = resonant_protocol::crypto::Handshake::initiate();
+    let capability = Capability {
+        method: "capability".to_string(),
+        v: 1,
+        agreed_proto: 0,
+        d_model: 4096,
+        embedding_space_id: "universal-llm-v3".to_string(),
+        space_hash32: 2451163210,
+        compress: vec!["zstd".to_string()],
+        crypto: vec!["xchacha20poly1305".to_string()],
+        supports: serde_json::json!({
+            "critique": true,
+            "dtype": ["f16", "i8", "q4", "sparse"]
+        }),
+        x25519_pub: Some(our_pub.to_bytes()),
+        compression_threshold: DEFAULT_COMPRESSION_THRESHOLD,
+        supported_protocols: SUPPORTED_PROTOCOLS.to_vec(),
+    };
+
+    let sync_packet = SyncPacket {
+        capability_json: serde_json::to_string(&capability)?,
+    };
+    let mut cap_bytes = Vec::new();
+    sync_packet.write_to(&mut cap_bytes)?;
 
-    let cap_bytes = serde_json::to_vec(&capability)?;
     let sync_frame = VFrame {
         hdr: VFrameHeader {
+            network: Network::Main,
             version: 1,
             mtype: MsgType::Sync,
             flags: Flags::empty(),
@@ -30,6 +53,8 @@ fn main() -> anyhow::Result<()> {
             slice_len: vec![cap_bytes.len() as u32],
             space_hash32: 2451163210,
             modality: Modality::Text,
+            fragment_idx: 0,
+            fragment_total: 1,
         },
         slices: vec![(
             SliceMeta {
@@ -54,6 +79,7 @@ fn main() -> anyhow::Result<()> {
 
     let think_frame = VFrame {
         hdr: VFrameHeader {
+            network: Network::Main,
             version: 1,
             mtype: MsgType::Think,
             flags: Flags::ZSTD, // Enable compression
@@ -63,6 +89,8 @@ fn main() -> anyhow::Result<()> {
             slice_len: vec![payload.len() as u32],
             space_hash32: 2451163210,
             modality: Modality::Text,
+            fragment_idx: 0,
+            fragment_total: 1,
         },
         slices: vec![(
             SliceMeta {
@@ -83,41 +111,39 @@ fn main() -> anyhow::Result<()> {
     // Send Critique message
     println!("\n3️⃣  Sending Critique message...");
 
-    let divergence_vec = vec![0u8; 32]; // Small divergence vector
     let explanation = serde_json::json!({
         "note": "divergence at dims [3, 17]",
         "magnitude": 0.042
     });
-    let explain_bytes = serde_json::to_vec(&explanation)?;
+    let critique_packet = CritiquePacket {
+        divergence: vec![0u8; 32], // Small divergence vector
+        explanation_json: serde_json::to_string(&explanation)?,
+    };
+    let mut critique_bytes_payload = Vec::new();
+    critique_packet.write_to(&mut critique_bytes_payload)?;
 
     let critique_frame = VFrame {
         hdr: VFrameHeader {
+            network: Network::Main,
             version: 1,
             mtype: MsgType::Critique,
             flags: Flags::empty(),
             stream_id,
             frame_seq: 2,
-            num_slices: 2,
-            slice_len: vec![divergence_vec.len() as u32, explain_bytes.len() as u32],
+            num_slices: 1,
+            slice_len: vec![critique_bytes_payload.len() as u32],
             space_hash32: 2451163210,
             modality: Modality::Text,
+            fragment_idx: 0,
+            fragment_total: 1,
         },
-        slices: vec![
-            (
-                SliceMeta {
-                    dtype: DType::F16,
-                    shape: vec![16],
-                },
-                divergence_vec,
-            ),
-            (
-                SliceMeta {
-                    dtype: DType::I8,
-                    shape: vec![explain_bytes.len() as u32],
-                },
-                explain_bytes,
-            ),
-        ],
+        slices: vec![(
+            SliceMeta {
+                dtype: DType::I8,
+                shape: vec![critique_bytes_payload.len() as u32],
+            },
+            critique_bytes_payload,
+        )],
         crc32: 0,
     };
 
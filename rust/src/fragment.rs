@@ -0,0 +1,448 @@
+//! Fragmentation for logical V-Frames too large for one datagram.
+//!
+//! A receiver's `recv_from` buffer caps a single read at one datagram, but an encoded
+//! `VFrame` carrying a full hidden state easily exceeds the path MTU. [`fragment`]
+//! splits an already-encoded frame's bytes into ordered `Flags::FRAGMENT` pieces that
+//! share the original `stream_id`/`frame_seq`, and [`Reassembler`] buffers pieces on
+//! the receive side until it can hand back the reconstructed bytes for the caller to
+//! decode via `VFrame::decode`/`VFrame::decode_encrypted`.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, ensure, Context};
+
+use crate::{
+    strong_tail_hash, DType, Flags, Modality, MsgType, Network, SliceMeta, VFrame, VFrameHeader,
+};
+
+/// Default per-fragment payload size, sized to stay clear of typical Ethernet/UDP path
+/// MTUs once the V-Frame header and IP/UDP overhead are accounted for.
+pub const DEFAULT_FRAGMENT_SIZE: usize = 1200;
+
+/// Default time a partial reassembly is kept before [`Reassembler::insert`] evicts it,
+/// so a stream missing its last fragment doesn't hold memory forever.
+pub const DEFAULT_REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Upper bound on `VFrameHeader::fragment_total` that [`Reassembler::insert`] will
+/// accept. `fragment_total` comes straight off the wire and directly sizes a
+/// `Vec<Option<Vec<u8>>>`, so without a cap a single small datagram claiming a
+/// near-`u32::MAX` fragment count can force a multi-gigabyte allocation and abort the
+/// process. At `DEFAULT_FRAGMENT_SIZE` bytes per fragment this still covers logical
+/// frames well beyond the 64 KiB single-datagram V-Frame cap.
+pub const MAX_FRAGMENT_COUNT: u32 = 65_536;
+
+/// Split `encoded` (the output of [`VFrame::encode`]/[`VFrame::encode_encrypted`] for a
+/// logical frame too big for one datagram) into ordered `Flags::FRAGMENT` V-Frames of
+/// at most `fragment_size` bytes each, sharing `stream_id`/`frame_seq` with the logical
+/// frame so [`Reassembler`] can group them back together. The last fragment also sets
+/// `Flags::STRONG_TAIL` and appends the xxh3 hash (see [`strong_tail_hash`]) of the
+/// full `encoded` buffer to its payload, so the receiver can detect reassembled bytes
+/// that differ from what was fragmented before trusting them.
+pub fn fragment(
+    encoded: &[u8],
+    mtype: MsgType,
+    stream_id: u32,
+    frame_seq: u64,
+    fragment_size: usize,
+) -> anyhow::Result<Vec<VFrame>> {
+    ensure!(!encoded.is_empty(), "cannot fragment an empty frame");
+    ensure!(fragment_size > 0, "fragment_size must be positive");
+
+    let chunks: Vec<&[u8]> = encoded.chunks(fragment_size).collect();
+    let fragment_total =
+        u32::try_from(chunks.len()).context("too many fragments to fit in fragment_total")?;
+    let tail_hash = strong_tail_hash(encoded);
+    let last_idx = chunks.len() - 1;
+
+    Ok(chunks
+        .into_iter()
+        .enumerate()
+        .map(|(idx, chunk)| {
+            let mut payload = chunk.to_vec();
+            let mut flags = Flags::FRAGMENT;
+            if idx == last_idx {
+                flags |= Flags::STRONG_TAIL;
+                payload.extend_from_slice(&tail_hash.to_le_bytes());
+            }
+
+            VFrame {
+                hdr: VFrameHeader {
+                    network: Network::Main,
+                    version: 1,
+                    mtype,
+                    flags,
+                    stream_id,
+                    frame_seq,
+                    num_slices: 1,
+                    slice_len: vec![payload.len() as u32],
+                    space_hash32: 0,
+                    modality: Modality::Mixed,
+                    fragment_idx: idx as u32,
+                    fragment_total,
+                },
+                slices: vec![(
+                    SliceMeta {
+                        dtype: DType::I8,
+                        shape: vec![payload.len() as u32],
+                    },
+                    payload,
+                )],
+                crc32: 0,
+            }
+        })
+        .collect())
+}
+
+/// The bytes [`Reassembler::insert`] reassembled don't match the `STRONG_TAIL` hash the
+/// last fragment carried, i.e. the contents of at least one fragment differ from the
+/// bytes the sender fragmented. Out-of-order arrival alone does not cause this, since
+/// each chunk is placed by its `fragment_idx`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReassemblyHashMismatch {
+    pub stream_id: u32,
+    pub frame_seq: u64,
+}
+
+impl std::fmt::Display for ReassemblyHashMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "STRONG_TAIL mismatch reassembling stream {:#x} frame_seq {}: reassembled bytes do not match the sender's hash",
+            self.stream_id, self.frame_seq
+        )
+    }
+}
+
+impl std::error::Error for ReassemblyHashMismatch {}
+
+/// Flat bitset over fragment indices `0..total`, tracking which have arrived so
+/// [`Reassembler::insert`] can tell when a logical frame is complete without rescanning
+/// its chunk buffer.
+struct FragmentBitset {
+    words: Vec<u64>,
+    received: u32,
+}
+
+impl FragmentBitset {
+    fn new(total: u32) -> Self {
+        let word_count = (total as usize).div_ceil(64).max(1);
+        Self {
+            words: vec![0u64; word_count],
+            received: 0,
+        }
+    }
+
+    /// Mark `idx` as received, returning whether this is the first time it was seen.
+    fn mark(&mut self, idx: u32) -> bool {
+        let word = idx as usize / 64;
+        let bit = 1u64 << (idx % 64);
+        let first_time = self.words[word] & bit == 0;
+        self.words[word] |= bit;
+        if first_time {
+            self.received += 1;
+        }
+        first_time
+    }
+
+    fn is_complete(&self, total: u32) -> bool {
+        self.received == total
+    }
+}
+
+/// One logical frame's in-progress reassembly.
+struct PartialFrame {
+    fragment_total: u32,
+    chunks: Vec<Option<Vec<u8>>>,
+    bitset: FragmentBitset,
+    tail_hash: Option<u64>,
+    last_seen: Instant,
+}
+
+impl PartialFrame {
+    fn new(fragment_total: u32) -> Self {
+        Self {
+            fragment_total,
+            chunks: vec![None; fragment_total as usize],
+            bitset: FragmentBitset::new(fragment_total),
+            tail_hash: None,
+            last_seen: Instant::now(),
+        }
+    }
+}
+
+/// Receive-side state for reassembling `Flags::FRAGMENT` pieces back into their
+/// logical `VFrame`, keyed by `(stream_id, frame_seq)`.
+pub struct Reassembler {
+    timeout: Duration,
+    partial: HashMap<(u32, u64), PartialFrame>,
+}
+
+impl Reassembler {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            partial: HashMap::new(),
+        }
+    }
+
+    /// Feed one fragment produced by [`fragment`]. Returns `Ok(Some(bytes))` once every
+    /// fragment for its `(stream_id, frame_seq)` has arrived and the reassembled bytes
+    /// pass the `STRONG_TAIL` hash check, `Ok(None)` while more fragments are still
+    /// expected. Also evicts any partial reassembly that has sat idle past this
+    /// `Reassembler`'s timeout.
+    ///
+    /// Returns the reassembled bytes rather than a decoded `VFrame`, since the original
+    /// frame may have been `Flags::XCHACHA`-sealed (via `VFrame::encode_encrypted`)
+    /// before it was fragmented — only the caller knows whether to hand the bytes to
+    /// `VFrame::decode` or `VFrame::decode_encrypted`.
+    pub fn insert(&mut self, frame: &VFrame) -> anyhow::Result<Option<Vec<u8>>> {
+        ensure!(
+            frame.hdr.flags.contains(Flags::FRAGMENT),
+            "frame is not FRAGMENT-flagged"
+        );
+
+        self.evict_expired();
+
+        let fragment_total = frame.hdr.fragment_total;
+        ensure!(fragment_total > 0, "fragment_total must be at least 1");
+        ensure!(
+            fragment_total <= MAX_FRAGMENT_COUNT,
+            "fragment_total {} exceeds MAX_FRAGMENT_COUNT ({})",
+            fragment_total,
+            MAX_FRAGMENT_COUNT
+        );
+        ensure!(
+            frame.hdr.fragment_idx < fragment_total,
+            "fragment_idx {} out of range for fragment_total {}",
+            frame.hdr.fragment_idx,
+            fragment_total
+        );
+
+        let key = (frame.hdr.stream_id, frame.hdr.frame_seq);
+        let partial = self
+            .partial
+            .entry(key)
+            .or_insert_with(|| PartialFrame::new(fragment_total));
+        ensure!(
+            partial.fragment_total == fragment_total,
+            "fragment_total changed mid-reassembly for stream {:#x} frame_seq {} ({} then {})",
+            key.0,
+            key.1,
+            partial.fragment_total,
+            fragment_total
+        );
+
+        let (_, payload) = frame
+            .slices
+            .first()
+            .ok_or_else(|| anyhow!("FRAGMENT frame carries no payload slice"))?;
+        let mut chunk = payload.clone();
+
+        if frame.hdr.flags.contains(Flags::STRONG_TAIL) {
+            ensure!(
+                chunk.len() >= 8,
+                "final fragment payload too short to carry its STRONG_TAIL hash"
+            );
+            let split_at = chunk.len() - 8;
+            let hash_bytes: [u8; 8] = chunk[split_at..].try_into().unwrap();
+            partial.tail_hash = Some(u64::from_le_bytes(hash_bytes));
+            chunk.truncate(split_at);
+        }
+
+        let idx = frame.hdr.fragment_idx as usize;
+        partial.bitset.mark(frame.hdr.fragment_idx);
+        partial.chunks[idx] = Some(chunk);
+        partial.last_seen = Instant::now();
+
+        if !partial.bitset.is_complete(fragment_total) {
+            return Ok(None);
+        }
+
+        let partial = self.partial.remove(&key).unwrap();
+        let mut reassembled = Vec::new();
+        for chunk in partial.chunks {
+            reassembled
+                .extend_from_slice(&chunk.ok_or_else(|| anyhow!("internal error: complete fragment bitset but missing chunk"))?);
+        }
+
+        if let Some(expected) = partial.tail_hash {
+            let actual = strong_tail_hash(&reassembled);
+            if actual != expected {
+                return Err(anyhow::Error::new(ReassemblyHashMismatch {
+                    stream_id: key.0,
+                    frame_seq: key.1,
+                }));
+            }
+        }
+
+        Ok(Some(reassembled))
+    }
+
+    /// Drop any partial reassembly that hasn't seen a fragment within this
+    /// `Reassembler`'s timeout.
+    fn evict_expired(&mut self) {
+        let timeout = self.timeout;
+        self.partial
+            .retain(|_, partial| partial.last_seen.elapsed() < timeout);
+    }
+}
+
+impl Default for Reassembler {
+    fn default() -> Self {
+        Self::new(DEFAULT_REASSEMBLY_TIMEOUT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DType as D, Modality as Mod, SliceMeta as SM, VFrameHeader as VH};
+
+    fn logical_frame(stream_id: u32, frame_seq: u64, payload_len: usize) -> VFrame {
+        VFrame {
+            hdr: VH {
+                network: Network::Main,
+                version: 1,
+                mtype: MsgType::Think,
+                flags: Flags::empty(),
+                stream_id,
+                frame_seq,
+                num_slices: 1,
+                slice_len: vec![payload_len as u32],
+                space_hash32: 2451163210,
+                modality: Mod::Text,
+                fragment_idx: 0,
+                fragment_total: 1,
+            },
+            slices: vec![(
+                SM {
+                    dtype: D::F16,
+                    shape: vec![(payload_len / 2) as u32],
+                },
+                vec![0xAB; payload_len],
+            )],
+            crc32: 0,
+        }
+    }
+
+    #[test]
+    fn test_fragment_and_reassemble_roundtrip() {
+        let frame = logical_frame(0x1234, 9, 10_000);
+        let encoded = frame.encode().unwrap();
+
+        let fragments = fragment(&encoded, MsgType::Think, 0x1234, 9, 1200).unwrap();
+        assert!(fragments.len() > 1);
+        for (idx, f) in fragments.iter().enumerate() {
+            assert_eq!(f.hdr.fragment_idx, idx as u32);
+            assert_eq!(f.hdr.fragment_total, fragments.len() as u32);
+            assert!(f.hdr.flags.contains(Flags::FRAGMENT));
+        }
+        assert!(fragments.last().unwrap().hdr.flags.contains(Flags::STRONG_TAIL));
+
+        let mut reassembler = Reassembler::new(Duration::from_secs(5));
+        let mut reassembled = None;
+        for f in &fragments {
+            reassembled = reassembler.insert(f).unwrap();
+        }
+
+        let reassembled = reassembled.expect("frame should reassemble once the last fragment arrives");
+        let reassembled = VFrame::decode(&reassembled).unwrap();
+        assert_eq!(reassembled.hdr.stream_id, 0x1234);
+        assert_eq!(reassembled.hdr.frame_seq, 9);
+        assert_eq!(reassembled.slices[0].1.len(), 10_000);
+    }
+
+    #[test]
+    fn test_fragment_and_reassemble_roundtrip_encrypted() {
+        let key = [7u8; 32];
+        let mut frame = logical_frame(0x4321, 4, 10_000);
+        frame.hdr.flags |= Flags::XCHACHA;
+        let encoded = frame.encode_encrypted(crate::DEFAULT_COMPRESSION_THRESHOLD, &key).unwrap();
+
+        let fragments = fragment(&encoded, MsgType::Think, 0x4321, 4, 1200).unwrap();
+        assert!(fragments.len() > 1);
+
+        let mut reassembler = Reassembler::new(Duration::from_secs(5));
+        let mut reassembled = None;
+        for f in &fragments {
+            reassembled = reassembler.insert(f).unwrap();
+        }
+
+        let reassembled = reassembled.expect("frame should reassemble once the last fragment arrives");
+        let decoded = VFrame::decode_encrypted(&reassembled, &key).unwrap();
+        assert_eq!(decoded.hdr.stream_id, 0x4321);
+        assert_eq!(decoded.slices[0].1.len(), 10_000);
+    }
+
+    #[test]
+    fn test_reassemble_tolerates_out_of_order_fragments() {
+        let frame = logical_frame(0xAAAA, 3, 5_000);
+        let encoded = frame.encode().unwrap();
+        let mut fragments = fragment(&encoded, MsgType::Think, 0xAAAA, 3, 1200).unwrap();
+        fragments.reverse();
+
+        let mut reassembler = Reassembler::new(Duration::from_secs(5));
+        let mut reassembled = None;
+        for f in &fragments {
+            reassembled = reassembler.insert(f).unwrap();
+        }
+
+        assert!(reassembled.is_some());
+    }
+
+    #[test]
+    fn test_reassemble_rejects_tail_hash_mismatch() {
+        let frame = logical_frame(0x5, 1, 5_000);
+        let encoded = frame.encode().unwrap();
+        let mut fragments = fragment(&encoded, MsgType::Think, 0x5, 1, 1200).unwrap();
+
+        // Corrupt a byte in an earlier fragment's payload so the reassembled bytes no
+        // longer match the tail hash carried by the last fragment.
+        fragments[0].slices[0].1[0] ^= 0xFF;
+
+        let mut reassembler = Reassembler::new(Duration::from_secs(5));
+        let mut result = Ok(None);
+        for f in &fragments {
+            result = reassembler.insert(f);
+            if result.is_err() {
+                break;
+            }
+        }
+
+        let err = result.unwrap_err();
+        assert!(err.downcast_ref::<ReassemblyHashMismatch>().is_some());
+    }
+
+    #[test]
+    fn test_reassembler_evicts_stale_partial_frame() {
+        let frame = logical_frame(0x7, 2, 5_000);
+        let encoded = frame.encode().unwrap();
+        let fragments = fragment(&encoded, MsgType::Think, 0x7, 2, 1200).unwrap();
+        assert!(fragments.len() > 1, "test needs at least 2 fragments");
+
+        let mut reassembler = Reassembler::new(Duration::from_millis(1));
+        assert!(reassembler.insert(&fragments[0]).unwrap().is_none());
+        std::thread::sleep(Duration::from_millis(20));
+
+        // The stale partial is evicted on the next insert, so feeding the remaining
+        // fragments starts a fresh reassembly rather than completing the old one.
+        for f in &fragments[1..] {
+            reassembler.insert(f).unwrap();
+        }
+        assert_eq!(reassembler.partial.len(), 1);
+    }
+
+    #[test]
+    fn test_reassembler_rejects_oversized_fragment_total() {
+        let mut frame = logical_frame(0x8, 1, 100);
+        frame.hdr.flags = Flags::FRAGMENT;
+        frame.hdr.fragment_idx = 0;
+        frame.hdr.fragment_total = MAX_FRAGMENT_COUNT + 1;
+
+        let mut reassembler = Reassembler::new(Duration::from_secs(5));
+        let err = reassembler.insert(&frame).unwrap_err();
+        assert!(err.to_string().contains("exceeds MAX_FRAGMENT_COUNT"));
+    }
+}
@@ -7,9 +7,15 @@ use std::{
 };
 use xxhash_rust::xxh3::xxh3_64;
 
+pub mod codec;
 pub mod compress;
 pub mod crypto;
 pub mod dtype;
+pub mod fragment;
+pub mod obfuscate;
+pub mod packet;
+pub mod transport;
+pub mod vstream;
 
 pub use dtype::{DType, Modality};
 
@@ -22,6 +28,8 @@ pub enum MsgType {
     Ask = 2,
     Sync = 3,
     Critique = 4,
+    /// V-Stream keepalive, also reused by [`obfuscate`] as cover traffic for padding.
+    Heart = 5,
 }
 
 impl MsgType {
@@ -32,11 +40,65 @@ impl MsgType {
             2 => Some(MsgType::Ask),
             3 => Some(MsgType::Sync),
             4 => Some(MsgType::Critique),
+            5 => Some(MsgType::Heart),
             _ => None,
         }
     }
 }
 
+/// Which deployment a V-Frame belongs to. Every frame is prefixed on the wire with a
+/// 4-byte magic naming its `Network`, borrowed from the network-magic idea in
+/// peer-to-peer protocols, so a receiver can tell a real frame boundary apart from a
+/// desynced byte stream or traffic meant for a different deployment before it ever
+/// touches [`MsgType`]/[`Flags`] parsing. See [`VFrame::find_next`] to resync past
+/// bytes that don't match.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Network {
+    Main,
+    Test,
+}
+
+impl Network {
+    /// This network's 4-byte wire magic.
+    pub fn magic(self) -> [u8; 4] {
+        match self {
+            Network::Main => *b"RSN1",
+            Network::Test => *b"RSNT",
+        }
+    }
+
+    /// Map a 4-byte wire prefix back to the `Network` it names, `None` if it matches
+    /// neither.
+    pub fn from_magic(magic: [u8; 4]) -> Option<Self> {
+        match magic {
+            m if m == Network::Main.magic() => Some(Network::Main),
+            m if m == Network::Test.magic() => Some(Network::Test),
+            _ => None,
+        }
+    }
+}
+
+/// Returned by [`VFrame::decode`]/[`VFrame::decode_encrypted`] (downcastable from the
+/// returned `anyhow::Error`) when the leading 4 bytes don't match any known [`Network`]
+/// magic, meaning the stream is desynced or carrying traffic from a foreign deployment
+/// rather than a malformed-but-genuine frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ForeignMagicError {
+    pub found: [u8; 4],
+}
+
+impl std::fmt::Display for ForeignMagicError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "frame magic {:?} matches no known Network; stream may be desynced or foreign",
+            self.found
+        )
+    }
+}
+
+impl std::error::Error for ForeignMagicError {}
+
 bitflags::bitflags! {
     /// Frame flags
     #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -44,12 +106,19 @@ bitflags::bitflags! {
         const ZSTD = 1 << 0;
         const XCHACHA = 1 << 1;
         const STRONG_TAIL = 1 << 2;
+        /// Stream carries DPI-resistant padding/jitter cover traffic, see [`obfuscate`].
+        const OBFUSCATE = 1 << 3;
+        /// Frame is one ordered piece of a larger logical frame, see [`fragment`].
+        const FRAGMENT = 1 << 4;
     }
 }
 
 /// V-Frame header structure
 #[derive(Clone, Debug)]
 pub struct VFrameHeader {
+    /// Deployment this frame belongs to, prefixed onto the wire as a 4-byte magic
+    /// ahead of `version`. See [`Network`].
+    pub network: Network,
     pub version: u8,
     pub mtype: MsgType,
     pub flags: Flags,
@@ -59,6 +128,12 @@ pub struct VFrameHeader {
     pub slice_len: Vec<u32>,
     pub space_hash32: u32,
     pub modality: Modality,
+    /// This piece's position within its logical frame when `Flags::FRAGMENT` is set,
+    /// `0` otherwise. See [`fragment`].
+    pub fragment_idx: u32,
+    /// Total number of pieces the logical frame was split into when `Flags::FRAGMENT`
+    /// is set, `1` otherwise.
+    pub fragment_total: u32,
 }
 
 /// Metadata for a single slice
@@ -77,8 +152,82 @@ pub struct VFrame {
 }
 
 impl VFrame {
-    /// Encode V-Frame to bytes
+    /// Encode V-Frame to bytes, compressing slices per [`DEFAULT_COMPRESSION_THRESHOLD`]
+    /// when `Flags::ZSTD` is set. Use [`VFrame::encode_with_threshold`] to apply a
+    /// threshold negotiated over the `Sync` handshake instead (see `Capability`), or
+    /// [`VFrame::encode_encrypted`] when `Flags::XCHACHA` is also set.
     pub fn encode(&self) -> anyhow::Result<Vec<u8>> {
+        self.encode_with_threshold(DEFAULT_COMPRESSION_THRESHOLD)
+    }
+
+    /// Encode V-Frame to bytes. When `Flags::ZSTD` is set, each slice payload at or
+    /// above `compression_threshold` bytes is zstd-compressed; a negative threshold
+    /// disables compression entirely even if the flag is set. Below the threshold a
+    /// slice is written raw. See [`encode_slice_payload`] for the on-wire framing.
+    ///
+    /// Bails if `Flags::XCHACHA` is set, since encrypting the slice region needs a
+    /// session key; use [`VFrame::encode_encrypted`] for those frames instead.
+    pub fn encode_with_threshold(&self, compression_threshold: i64) -> anyhow::Result<Vec<u8>> {
+        ensure!(
+            !self.hdr.flags.contains(Flags::XCHACHA),
+            "frame is XCHACHA-flagged; use VFrame::encode_encrypted instead"
+        );
+
+        let wire_payloads = self.build_wire_payloads(compression_threshold)?;
+        let mut buf = write_header(&self.hdr, &wire_payloads, None)?;
+        write_slices(&mut buf, &self.slices, &wire_payloads)?;
+        append_crc(&mut buf)?;
+        Ok(buf)
+    }
+
+    /// Encode V-Frame to bytes with the slice region sealed under XChaCha20-Poly1305,
+    /// as driven by `Flags::XCHACHA` (compressing per `Flags::ZSTD` first, same as
+    /// [`VFrame::encode_with_threshold`]). The nonce is derived from this frame's
+    /// `(stream_id, frame_seq)` via [`vstream::derive_frame_nonce`], and the plaintext
+    /// header is bound into the AEAD tag as associated data so a ciphertext can't be
+    /// replayed under a different header.
+    pub fn encode_encrypted(
+        &self,
+        compression_threshold: i64,
+        key: &[u8; 32],
+    ) -> anyhow::Result<Vec<u8>> {
+        ensure!(
+            self.hdr.flags.contains(Flags::XCHACHA),
+            "Flags::XCHACHA must be set to encode_encrypted"
+        );
+
+        let wire_payloads = self.build_wire_payloads(compression_threshold)?;
+        let mut plaintext = Vec::new();
+        write_slices(&mut plaintext, &self.slices, &wire_payloads)?;
+
+        // The sealed region's length (ciphertext + 16-byte Poly1305 tag) is written into
+        // the cleartext header so a stream decoder can find the next frame's boundary
+        // without decrypting — the slice region is opaque ciphertext to it, and
+        // `slice_len` alone undercounts by the per-slice dtype/shape metadata that's
+        // also sealed. See `codec::peek_frame_len`.
+        let sealed_len = (plaintext.len() + 16) as u32;
+        let header = write_header(&self.hdr, &wire_payloads, Some(sealed_len))?;
+
+        let nonce = vstream::derive_frame_nonce(self.hdr.stream_id, self.hdr.frame_seq);
+        let sealed = crypto::seal_xchacha_aad(key, &nonce, &plaintext, &header)?;
+        ensure!(
+            sealed.len() == sealed_len as usize,
+            "sealed region length {} does not match the declared header field {}",
+            sealed.len(),
+            sealed_len
+        );
+
+        let mut buf = header;
+        buf.extend_from_slice(&sealed);
+        append_crc(&mut buf)?;
+        Ok(buf)
+    }
+
+    /// Validate the frame against its own header and compute each slice's final
+    /// on-wire bytes (compressed per `Flags::ZSTD` or raw), since the header's
+    /// `slice_len` table must carry the on-wire length, not the original payload
+    /// length.
+    fn build_wire_payloads(&self, compression_threshold: i64) -> anyhow::Result<Vec<Vec<u8>>> {
         ensure!(
             self.hdr.num_slices as usize == self.slices.len(),
             "Header num_slices ({}) does not match payload count ({})",
@@ -98,29 +247,9 @@ impl VFrame {
             );
         }
 
-        let mut buf = Vec::new();
-
-        // Write header
-        buf.write_u8(self.hdr.version)?;
-        buf.write_u8(self.hdr.mtype as u8)?;
-        buf.write_u16::<LittleEndian>(self.hdr.flags.bits())?;
-        buf.write_u32::<LittleEndian>(self.hdr.stream_id)?;
-        buf.write_u64::<LittleEndian>(self.hdr.frame_seq)?;
-        buf.write_u64::<LittleEndian>(self.hdr.num_slices)?;
-
-        // Write slice lengths
-        if self.hdr.slice_len.len() == 1 {
-            buf.write_u32::<LittleEndian>(self.hdr.slice_len[0])?;
-        } else {
-            for &len in &self.hdr.slice_len {
-                buf.write_u32::<LittleEndian>(len)?;
-            }
-        }
-
-        buf.write_u32::<LittleEndian>(self.hdr.space_hash32)?;
-        buf.write_u8(self.hdr.modality as u8)?;
+        let zstd_enabled = self.hdr.flags.contains(Flags::ZSTD);
 
-        // Write slices
+        let mut wire_payloads = Vec::with_capacity(self.slices.len());
         for (idx, (meta, payload)) in self.slices.iter().enumerate() {
             let declared_len = if self.hdr.slice_len.len() == 1 {
                 self.hdr.slice_len[0] as usize
@@ -147,152 +276,346 @@ impl VFrame {
                 );
             }
 
-            buf.write_u8(meta.dtype as u8)?;
-            buf.write_u8(meta.shape.len() as u8)?;
-            for &dim in &meta.shape {
-                buf.write_u32::<LittleEndian>(dim)?;
-            }
-            buf.extend_from_slice(payload);
+            wire_payloads.push(encode_slice_payload(
+                payload,
+                zstd_enabled,
+                compression_threshold,
+            )?);
         }
 
-        // Calculate and append CRC32
-        let crc = crc32fast::hash(&buf);
-        buf.write_u32::<LittleEndian>(crc)?;
-
-        Ok(buf)
+        Ok(wire_payloads)
     }
 
-    /// Decode V-Frame from bytes
+    /// Decode V-Frame from bytes. Bails if `Flags::XCHACHA` is set, since the slice
+    /// region is sealed and needs a session key; use [`VFrame::decode_encrypted`] for
+    /// those frames instead.
     pub fn decode(data: &[u8]) -> anyhow::Result<Self> {
+        let (hdr, slices_start, _sealed_len) = parse_header(data)?;
+
         ensure!(
-            data.len() >= 4 + 1 + 1 + 2 + 4 + 8 + 8,
-            "Frame is too short to contain mandatory header"
+            !hdr.flags.contains(Flags::XCHACHA),
+            "frame is XCHACHA-flagged; use VFrame::decode_encrypted instead"
         );
 
-        let mut cursor = Cursor::new(data);
+        ensure!(
+            data.len() >= slices_start + 4,
+            "Frame is too short to contain its slice region and CRC32"
+        );
 
-        // Read header
-        let version = cursor.read_u8().context("Failed to read version")?;
-        let mtype = MsgType::from_u8(cursor.read_u8().context("Failed to read message type")?)
-            .ok_or_else(|| anyhow!("Invalid message type"))?;
-        let flags = Flags::from_bits(
-            cursor
-                .read_u16::<LittleEndian>()
-                .context("Failed to read flags")?,
-        )
-        .ok_or_else(|| anyhow!("Invalid flags"))?;
-        let stream_id = cursor
+        let region = &data[slices_start..data.len() - 4];
+        let slices = parse_slices(region, &hdr)?;
+        let crc32 = verify_crc(data)?;
+
+        Ok(VFrame {
+            hdr,
+            slices,
+            crc32,
+        })
+    }
+
+    /// Decode a V-Frame whose slice region was sealed by [`VFrame::encode_encrypted`].
+    /// Verifies the CRC32 trailer first, so plain transit corruption is rejected before
+    /// spending an AEAD open on it, then re-derives the nonce from `(stream_id,
+    /// frame_seq)` and the plaintext header the same way the encoder did, authenticates
+    /// and decrypts the slice region, and parses it. Returns [`crypto::FrameAuthError`]
+    /// (downcastable from the returned `anyhow::Error`) if the AEAD tag fails to verify.
+    pub fn decode_encrypted(data: &[u8], key: &[u8; 32]) -> anyhow::Result<Self> {
+        let (hdr, slices_start, sealed_len) = parse_header(data)?;
+
+        ensure!(
+            hdr.flags.contains(Flags::XCHACHA),
+            "frame is not XCHACHA-flagged; use VFrame::decode instead"
+        );
+
+        ensure!(
+            data.len() >= slices_start + 4,
+            "Frame is too short to contain its sealed slice region and CRC32"
+        );
+
+        // Catch plain corruption with the cheap CRC32 check before spending an AEAD
+        // open on bytes that may not even be the ones the sender sealed.
+        let crc32 = verify_crc(data)?;
+
+        let header_bytes = &data[..slices_start];
+        let sealed = &data[slices_start..data.len() - 4];
+        let sealed_len = sealed_len.context("XCHACHA-flagged frame is missing its sealed_len header field")?;
+        ensure!(
+            sealed.len() == sealed_len as usize,
+            "sealed region is {} bytes, header declares {}",
+            sealed.len(),
+            sealed_len
+        );
+        let nonce = vstream::derive_frame_nonce(hdr.stream_id, hdr.frame_seq);
+        let plaintext = crypto::open_xchacha_aad(key, &nonce, sealed, header_bytes)?;
+
+        let slices = parse_slices(&plaintext, &hdr)?;
+
+        Ok(VFrame {
+            hdr,
+            slices,
+            crc32,
+        })
+    }
+
+    /// Scan `data` for the next byte offset carrying a known [`Network`] magic, letting
+    /// a stream decoder skip corrupt or foreign bytes and resynchronize on the next real
+    /// frame boundary instead of tearing down the connection on a parse error. Returns
+    /// `None` if no known magic appears anywhere in `data`.
+    pub fn find_next(data: &[u8]) -> Option<usize> {
+        if data.len() < 4 {
+            return None;
+        }
+        (0..=data.len() - 4)
+            .find(|&i| Network::from_magic(data[i..i + 4].try_into().unwrap()).is_some())
+    }
+}
+
+/// Parse the mandatory V-Frame header (the leading `Network` magic through
+/// `fragment_total`, plus the `sealed_len` field `Flags::XCHACHA` frames carry), returning it alongside
+/// the byte offset where the slice region begins and, for an XCHACHA frame, the
+/// declared sealed-region length (`None` otherwise). Bails with a [`ForeignMagicError`]
+/// if the magic doesn't name a known `Network`. Shared by [`VFrame::decode`] and
+/// [`VFrame::decode_encrypted`], since the header is always plaintext even when the
+/// slice region is sealed.
+fn parse_header(data: &[u8]) -> anyhow::Result<(VFrameHeader, usize, Option<u32>)> {
+    ensure!(
+        data.len() >= 4 + 4 + 1 + 1 + 2 + 4 + 8 + 8,
+        "Frame is too short to contain mandatory header"
+    );
+
+    let magic: [u8; 4] = data[..4].try_into().unwrap();
+    let network = Network::from_magic(magic)
+        .ok_or_else(|| anyhow::Error::new(ForeignMagicError { found: magic }))?;
+
+    let mut cursor = Cursor::new(&data[4..]);
+
+    let version = cursor.read_u8().context("Failed to read version")?;
+    let mtype = MsgType::from_u8(cursor.read_u8().context("Failed to read message type")?)
+        .ok_or_else(|| anyhow!("Invalid message type"))?;
+    let flags = Flags::from_bits(
+        cursor
+            .read_u16::<LittleEndian>()
+            .context("Failed to read flags")?,
+    )
+    .ok_or_else(|| anyhow!("Invalid flags"))?;
+    let stream_id = cursor
+        .read_u32::<LittleEndian>()
+        .context("Failed to read stream_id")?;
+    let frame_seq = cursor
+        .read_u64::<LittleEndian>()
+        .context("Failed to read frame_seq")?;
+    let num_slices = cursor
+        .read_u64::<LittleEndian>()
+        .context("Failed to read num_slices")?;
+
+    let slice_len = if num_slices == 1 {
+        vec![cursor
             .read_u32::<LittleEndian>()
-            .context("Failed to read stream_id")?;
-        let frame_seq = cursor
-            .read_u64::<LittleEndian>()
-            .context("Failed to read frame_seq")?;
-        let num_slices = cursor
-            .read_u64::<LittleEndian>()
-            .context("Failed to read num_slices")?;
-
-        // Read slice lengths
-        let slice_len = if num_slices == 1 {
-            vec![cursor
+            .context("Failed to read slice_len")?]
+    } else {
+        let mut lens = Vec::new();
+        for _ in 0..num_slices {
+            lens.push(
+                cursor
+                    .read_u32::<LittleEndian>()
+                    .context("Failed to read slice_len")?,
+            );
+        }
+        lens
+    };
+
+    ensure!(
+        !slice_len.is_empty(),
+        "slice_len field is empty, frame is malformed"
+    );
+
+    let space_hash32 = cursor
+        .read_u32::<LittleEndian>()
+        .context("Failed to read space_hash32")?;
+    let modality = Modality::from_u8(cursor.read_u8().context("Failed to read modality")?)
+        .ok_or_else(|| anyhow!("Invalid modality"))?;
+    let fragment_idx = cursor
+        .read_u32::<LittleEndian>()
+        .context("Failed to read fragment_idx")?;
+    let fragment_total = cursor
+        .read_u32::<LittleEndian>()
+        .context("Failed to read fragment_total")?;
+
+    // An XCHACHA frame's slice region is an opaque ciphertext blob, so a stream decoder
+    // can't size it from `slice_len` (which only ever tracks plaintext payload bytes,
+    // not the dtype/shape metadata sealed alongside them). Carry its length here,
+    // right after the rest of the plaintext header, so `codec::peek_frame_len` can find
+    // the next frame's boundary without decrypting.
+    let sealed_len = if flags.contains(Flags::XCHACHA) {
+        Some(
+            cursor
                 .read_u32::<LittleEndian>()
-                .context("Failed to read slice_len")?]
+                .context("Failed to read sealed_len")?,
+        )
+    } else {
+        None
+    };
+
+    Ok((
+        VFrameHeader {
+            network,
+            version,
+            mtype,
+            flags,
+            stream_id,
+            frame_seq,
+            num_slices,
+            slice_len,
+            space_hash32,
+            modality,
+            fragment_idx,
+            fragment_total,
+        },
+        4 + cursor.position() as usize,
+        sealed_len,
+    ))
+}
+
+/// Parse the slice region following `hdr`'s header, decompressing each slice per
+/// `Flags::ZSTD`. `region` holds only the slice bytes (plaintext either way — the
+/// caller has already decrypted it if `Flags::XCHACHA` was set), with the CRC32
+/// trailer excluded.
+fn parse_slices(region: &[u8], hdr: &VFrameHeader) -> anyhow::Result<Vec<(SliceMeta, Vec<u8>)>> {
+    let mut cursor = Cursor::new(region);
+    let mut slices = Vec::new();
+
+    for slice_idx in 0..hdr.num_slices {
+        let dtype = DType::from_u8(cursor.read_u8().context("Failed to read dtype")?)
+            .ok_or_else(|| anyhow!("Invalid dtype"))?;
+        let shape_len = cursor.read_u8().context("Failed to read shape length")?;
+        let mut shape = Vec::new();
+        for _ in 0..shape_len {
+            shape.push(
+                cursor
+                    .read_u32::<LittleEndian>()
+                    .context("Failed to read shape dimension")?,
+            );
+        }
+
+        let declared_len = if hdr.slice_len.len() == 1 {
+            hdr.slice_len[0] as usize
         } else {
-            let mut lens = Vec::new();
-            for _ in 0..num_slices {
-                lens.push(
-                    cursor
-                        .read_u32::<LittleEndian>()
-                        .context("Failed to read slice_len")?,
-                );
-            }
-            lens
+            let idx = usize::try_from(slice_idx).context("num_slices exceeds usize")?;
+            hdr.slice_len[idx] as usize
         };
 
         ensure!(
-            !slice_len.is_empty(),
-            "slice_len field is empty, frame is malformed"
+            declared_len <= region.len(),
+            "Declared slice length {} exceeds slice region size",
+            declared_len
         );
 
-        let space_hash32 = cursor
-            .read_u32::<LittleEndian>()
-            .context("Failed to read space_hash32")?;
-        let modality = Modality::from_u8(cursor.read_u8().context("Failed to read modality")?)
-            .ok_or_else(|| anyhow!("Invalid modality"))?;
-
-        // Read slices
-        let mut slices = Vec::new();
-        for slice_idx in 0..num_slices {
-            let dtype = DType::from_u8(cursor.read_u8().context("Failed to read dtype")?)
-                .ok_or_else(|| anyhow!("Invalid dtype"))?;
-            let shape_len = cursor.read_u8().context("Failed to read shape length")?;
-            let mut shape = Vec::new();
-            for _ in 0..shape_len {
-                shape.push(
-                    cursor
-                        .read_u32::<LittleEndian>()
-                        .context("Failed to read shape dimension")?,
-                );
-            }
+        let mut wire_payload = vec![0u8; declared_len];
+        cursor
+            .read_exact(&mut wire_payload)
+            .context("Failed to read slice payload")?;
 
-            let declared_len = if slice_len.len() == 1 {
-                slice_len[0] as usize
-            } else {
-                let idx = usize::try_from(slice_idx).context("num_slices exceeds usize")?;
-                slice_len[idx] as usize
-            };
+        let payload = decode_slice_payload(&wire_payload, hdr.flags.contains(Flags::ZSTD))?;
 
+        if let Some(expected) = expected_payload_size(dtype, &shape)? {
             ensure!(
-                declared_len <= data.len(),
-                "Declared slice length {} exceeds frame size",
-                declared_len
+                expected == payload.len(),
+                "dtype {:?} with shape {:?} expects {} bytes, got {} bytes",
+                dtype,
+                shape,
+                expected,
+                payload.len()
             );
+        }
 
-            if let Some(expected) = expected_payload_size(dtype, &shape)? {
-                ensure!(
-                    expected == declared_len,
-                    "dtype {:?} with shape {:?} expects {} bytes, header declares {} bytes",
-                    dtype,
-                    shape,
-                    expected,
-                    declared_len
-                );
-            }
+        slices.push((SliceMeta { dtype, shape }, payload));
+    }
 
-            let mut payload = vec![0u8; declared_len];
-            cursor
-                .read_exact(&mut payload)
-                .context("Failed to read slice payload")?;
+    Ok(slices)
+}
 
-            slices.push((SliceMeta { dtype, shape }, payload));
-        }
+/// Verify the CRC32 trailer against the rest of `data`, returning the carried value.
+fn verify_crc(data: &[u8]) -> anyhow::Result<u32> {
+    let mut crc_cursor = Cursor::new(&data[data.len() - 4..]);
+    let crc32 = crc_cursor
+        .read_u32::<LittleEndian>()
+        .context("Failed to read crc32")?;
+    let computed_crc = crc32fast::hash(&data[..data.len() - 4]);
 
-        // Verify CRC32
-        let crc32 = cursor
-            .read_u32::<LittleEndian>()
-            .context("Failed to read crc32")?;
-        let data_for_crc = &data[..data.len() - 4];
-        let computed_crc = crc32fast::hash(data_for_crc);
+    if crc32 != computed_crc {
+        bail!("CRC32 mismatch: expected {}, got {}", crc32, computed_crc);
+    }
+
+    Ok(crc32)
+}
+
+/// Write the mandatory header (the `Network` magic through `fragment_total`, with the
+/// slice-length table carrying each slice's final on-wire length). `sealed_len` must be
+/// `Some` iff `hdr.flags` contains `Flags::XCHACHA`, and carries the total byte length
+/// of the sealed slice region (ciphertext + Poly1305 tag) that follows this header, so
+/// a stream decoder can size the frame without decrypting it (see `codec::peek_frame_len`).
+fn write_header(
+    hdr: &VFrameHeader,
+    wire_payloads: &[Vec<u8>],
+    sealed_len: Option<u32>,
+) -> anyhow::Result<Vec<u8>> {
+    ensure!(
+        hdr.flags.contains(Flags::XCHACHA) == sealed_len.is_some(),
+        "sealed_len must be provided iff Flags::XCHACHA is set"
+    );
+
+    let mut buf = Vec::new();
 
-        if crc32 != computed_crc {
-            bail!("CRC32 mismatch: expected {}, got {}", crc32, computed_crc);
+    buf.extend_from_slice(&hdr.network.magic());
+    buf.write_u8(hdr.version)?;
+    buf.write_u8(hdr.mtype as u8)?;
+    buf.write_u16::<LittleEndian>(hdr.flags.bits())?;
+    buf.write_u32::<LittleEndian>(hdr.stream_id)?;
+    buf.write_u64::<LittleEndian>(hdr.frame_seq)?;
+    buf.write_u64::<LittleEndian>(hdr.num_slices)?;
+
+    if wire_payloads.len() == 1 {
+        buf.write_u32::<LittleEndian>(wire_payloads[0].len() as u32)?;
+    } else {
+        for payload in wire_payloads {
+            buf.write_u32::<LittleEndian>(payload.len() as u32)?;
         }
+    }
 
-        Ok(VFrame {
-            hdr: VFrameHeader {
-                version,
-                mtype,
-                flags,
-                stream_id,
-                frame_seq,
-                num_slices,
-                slice_len,
-                space_hash32,
-                modality,
-            },
-            slices,
-            crc32,
-        })
+    buf.write_u32::<LittleEndian>(hdr.space_hash32)?;
+    buf.write_u8(hdr.modality as u8)?;
+    buf.write_u32::<LittleEndian>(hdr.fragment_idx)?;
+    buf.write_u32::<LittleEndian>(hdr.fragment_total)?;
+
+    if let Some(sealed_len) = sealed_len {
+        buf.write_u32::<LittleEndian>(sealed_len)?;
     }
+
+    Ok(buf)
+}
+
+/// Append the slice region (dtype, shape, on-wire payload per slice) to `buf`.
+fn write_slices(
+    buf: &mut Vec<u8>,
+    slices: &[(SliceMeta, Vec<u8>)],
+    wire_payloads: &[Vec<u8>],
+) -> anyhow::Result<()> {
+    for ((meta, _), wire_payload) in slices.iter().zip(wire_payloads.iter()) {
+        buf.write_u8(meta.dtype as u8)?;
+        buf.write_u8(meta.shape.len() as u8)?;
+        for &dim in &meta.shape {
+            buf.write_u32::<LittleEndian>(dim)?;
+        }
+        buf.extend_from_slice(wire_payload);
+    }
+    Ok(())
+}
+
+/// Compute and append the trailing CRC32 over everything written to `buf` so far.
+fn append_crc(buf: &mut Vec<u8>) -> anyhow::Result<()> {
+    let crc = crc32fast::hash(buf);
+    buf.write_u32::<LittleEndian>(crc)?;
+    Ok(())
 }
 
 fn expected_payload_size(dtype: DType, shape: &[u32]) -> anyhow::Result<Option<usize>> {
@@ -322,6 +645,76 @@ fn expected_payload_size(dtype: DType, shape: &[u32]) -> anyhow::Result<Option<u
     Ok(size)
 }
 
+/// Default per-slice compression threshold (bytes) used by [`VFrame::encode`] when the
+/// caller hasn't negotiated one via `Capability::compression_threshold`.
+pub const DEFAULT_COMPRESSION_THRESHOLD: i64 = 256;
+
+/// On-wire framing for a `Flags::ZSTD` slice: `[1B compressed][4B original_len][body]`.
+/// `compressed=1` means `body` is a zstd frame that inflates to `original_len` bytes;
+/// `compressed=0` means `body` is already the `original_len`-byte raw payload. The
+/// explicit flag (rather than inferring from size) keeps decode unambiguous regardless
+/// of how compression affects a given payload's size.
+fn encode_slice_payload(
+    payload: &[u8],
+    zstd_enabled: bool,
+    compression_threshold: i64,
+) -> anyhow::Result<Vec<u8>> {
+    if !zstd_enabled {
+        return Ok(payload.to_vec());
+    }
+
+    let should_compress = compression_threshold >= 0 && payload.len() as i64 >= compression_threshold;
+
+    let mut framed = Vec::with_capacity(payload.len() + 5);
+    if should_compress {
+        let compressed =
+            compress::zstd_compress(payload, 0).context("Failed to zstd-compress slice payload")?;
+        framed.push(1u8);
+        framed.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        framed.extend_from_slice(&compressed);
+    } else {
+        framed.push(0u8);
+        framed.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        framed.extend_from_slice(payload);
+    }
+
+    Ok(framed)
+}
+
+/// Inverse of [`encode_slice_payload`]: transparently decompresses a `Flags::ZSTD`
+/// slice back to its original payload, verifying the decompressed (or raw) length
+/// matches what the sender declared.
+fn decode_slice_payload(wire_bytes: &[u8], zstd_enabled: bool) -> anyhow::Result<Vec<u8>> {
+    if !zstd_enabled {
+        return Ok(wire_bytes.to_vec());
+    }
+
+    ensure!(
+        wire_bytes.len() >= 5,
+        "ZSTD-flagged slice of {} bytes is too short for the compression header",
+        wire_bytes.len()
+    );
+
+    let compressed = wire_bytes[0] == 1;
+    let orig_len = u32::from_le_bytes(wire_bytes[1..5].try_into().unwrap()) as usize;
+    let body = &wire_bytes[5..];
+
+    let payload = if compressed {
+        compress::zstd_decompress(body).context("Failed to zstd-decompress slice payload")?
+    } else {
+        body.to_vec()
+    };
+
+    ensure!(
+        payload.len() == orig_len,
+        "slice payload of {} bytes does not match declared original length {}",
+        payload.len(),
+        orig_len
+    );
+
+    Ok(payload)
+}
+
 /// Capability handshake structure
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Capability {
@@ -334,6 +727,84 @@ pub struct Capability {
     pub compress: Vec<String>,
     pub crypto: Vec<String>,
     pub supports: serde_json::Value,
+    /// This side's ephemeral X25519 public key, advertised so the peer can complete
+    /// the key-agreement handshake and derive the session key (see [`crypto::Handshake`]).
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub x25519_pub: Option<[u8; 32]>,
+    /// Minimum slice payload size (bytes) this side will zstd-compress, modeled on
+    /// Minecraft's negotiated compression threshold: negative disables compression
+    /// entirely, `N` means "only compress payloads >= N bytes". Peers should negotiate
+    /// down to the smaller of the two advertised thresholds (see [`DEFAULT_COMPRESSION_THRESHOLD`]
+    /// for the value used when a peer doesn't advertise one).
+    #[serde(default = "default_compression_threshold")]
+    pub compression_threshold: i64,
+    /// All `VFrameHeader::version` values this side can decode, so [`Capability::negotiate`]
+    /// can pick the highest one both peers share instead of assuming `agreed_proto`
+    /// was already settled out-of-band (see [`SUPPORTED_PROTOCOLS`]).
+    #[serde(default = "default_supported_protocols")]
+    pub supported_protocols: Vec<u32>,
+}
+
+fn default_compression_threshold() -> i64 {
+    DEFAULT_COMPRESSION_THRESHOLD
+}
+
+fn default_supported_protocols() -> Vec<u32> {
+    SUPPORTED_PROTOCOLS.to_vec()
+}
+
+/// `VFrameHeader::version` values this build can decode. Bump when the wire format
+/// changes in a way older versions can't interpret, keeping the old value in the list
+/// as long as this build still speaks it.
+pub const SUPPORTED_PROTOCOLS: &[u32] = &[1];
+
+impl Capability {
+    /// Negotiate the protocol version to stamp onto subsequent outgoing frames: the
+    /// highest value present in both `self.supported_protocols` and
+    /// `peer.supported_protocols`. Errors if the two sides share no version, so a
+    /// mismatched peer fails fast at the `Sync` handshake instead of silently decoding
+    /// frames it can't interpret.
+    pub fn negotiate(&self, peer: &Capability) -> anyhow::Result<u32> {
+        self.supported_protocols
+            .iter()
+            .filter(|v| peer.supported_protocols.contains(v))
+            .max()
+            .copied()
+            .ok_or_else(|| {
+                anyhow!(
+                    "no protocol version in common: we support {:?}, peer supports {:?}",
+                    self.supported_protocols,
+                    peer.supported_protocols
+                )
+            })
+    }
+
+    /// Negotiate which compression algorithm to use with `peer`: the first entry of
+    /// `self.compress` (in this side's preference order) that `peer.compress` also
+    /// lists. Errors if there is no overlap.
+    pub fn negotiate_compress(&self, peer: &Capability) -> anyhow::Result<String> {
+        negotiate_algorithm(&self.compress, &peer.compress)
+    }
+
+    /// Negotiate which AEAD algorithm to use with `peer`, by the same rule as
+    /// [`Capability::negotiate_compress`].
+    pub fn negotiate_crypto(&self, peer: &Capability) -> anyhow::Result<String> {
+        negotiate_algorithm(&self.crypto, &peer.crypto)
+    }
+}
+
+/// Pick the first entry of `ours` (preference order) that also appears in `theirs`.
+fn negotiate_algorithm(ours: &[String], theirs: &[String]) -> anyhow::Result<String> {
+    ours.iter()
+        .find(|algo| theirs.contains(algo))
+        .cloned()
+        .ok_or_else(|| {
+            anyhow!(
+                "no algorithm in common: we support {:?}, peer supports {:?}",
+                ours,
+                theirs
+            )
+        })
 }
 
 /// Calculate strong tail hash for stream verification
@@ -349,6 +820,7 @@ mod tests {
     fn test_vframe_encode_decode() {
         let frame = VFrame {
             hdr: VFrameHeader {
+                network: Network::Main,
                 version: 1,
                 mtype: MsgType::Think,
                 flags: Flags::ZSTD,
@@ -358,6 +830,8 @@ mod tests {
                 slice_len: vec![8],
                 space_hash32: 2451163210,
                 modality: Modality::Text,
+                fragment_idx: 0,
+                fragment_total: 1,
             },
             slices: vec![(
                 SliceMeta {
@@ -381,6 +855,7 @@ mod tests {
     fn test_encode_rejects_mismatched_lengths() {
         let frame = VFrame {
             hdr: VFrameHeader {
+                network: Network::Main,
                 version: 1,
                 mtype: MsgType::Think,
                 flags: Flags::empty(),
@@ -390,6 +865,8 @@ mod tests {
                 slice_len: vec![4],
                 space_hash32: 0,
                 modality: Modality::Text,
+                fragment_idx: 0,
+                fragment_total: 1,
             },
             slices: vec![(
                 SliceMeta {
@@ -408,6 +885,7 @@ mod tests {
     fn test_q4_roundtrip_validates_shape() {
         let frame = VFrame {
             hdr: VFrameHeader {
+                network: Network::Main,
                 version: 1,
                 mtype: MsgType::Think,
                 flags: Flags::empty(),
@@ -417,6 +895,8 @@ mod tests {
                 slice_len: vec![4],
                 space_hash32: 2451163210,
                 modality: Modality::Text,
+                fragment_idx: 0,
+                fragment_total: 1,
             },
             slices: vec![(
                 SliceMeta {
@@ -438,6 +918,7 @@ mod tests {
     fn test_decode_rejects_invalid_len_vs_shape() {
         let frame = VFrame {
             hdr: VFrameHeader {
+                network: Network::Main,
                 version: 1,
                 mtype: MsgType::Think,
                 flags: Flags::empty(),
@@ -447,6 +928,8 @@ mod tests {
                 slice_len: vec![8],
                 space_hash32: 0,
                 modality: Modality::Text,
+                fragment_idx: 0,
+                fragment_total: 1,
             },
             slices: vec![(
                 SliceMeta {
@@ -459,7 +942,7 @@ mod tests {
         };
 
         let mut encoded = frame.encode().unwrap();
-        encoded[24..28].copy_from_slice(&(2u32).to_le_bytes());
+        encoded[28..32].copy_from_slice(&(2u32).to_le_bytes());
         let payload_len = frame.slices[0].1.len();
         let payload_offset = encoded.len() - 4 - payload_len;
         encoded.drain(payload_offset + 2..payload_offset + payload_len);
@@ -473,4 +956,310 @@ mod tests {
             "unexpected error: {err}"
         );
     }
+
+    #[test]
+    fn test_zstd_flag_compresses_above_threshold_and_roundtrips() {
+        let payload = vec![0u8; 4096]; // highly compressible, like the Think example's f16 state
+        let frame = VFrame {
+            hdr: VFrameHeader {
+                network: Network::Main,
+                version: 1,
+                mtype: MsgType::Think,
+                flags: Flags::ZSTD,
+                stream_id: 0x1234,
+                frame_seq: 1,
+                num_slices: 1,
+                slice_len: vec![payload.len() as u32],
+                space_hash32: 2451163210,
+                modality: Modality::Text,
+                fragment_idx: 0,
+                fragment_total: 1,
+            },
+            slices: vec![(
+                SliceMeta {
+                    dtype: DType::F16,
+                    shape: vec![2048],
+                },
+                payload.clone(),
+            )],
+            crc32: 0,
+        };
+
+        let encoded = frame.encode_with_threshold(256).unwrap();
+        assert!(
+            encoded.len() < payload.len(),
+            "zeroed payload above threshold should compress smaller than raw"
+        );
+
+        let decoded = VFrame::decode(&encoded).unwrap();
+        assert_eq!(decoded.slices[0].1, payload);
+    }
+
+    #[test]
+    fn test_zstd_flag_leaves_payload_below_threshold_uncompressed() {
+        let payload = vec![0u8; 8];
+        let frame = VFrame {
+            hdr: VFrameHeader {
+                network: Network::Main,
+                version: 1,
+                mtype: MsgType::Think,
+                flags: Flags::ZSTD,
+                stream_id: 0x1234,
+                frame_seq: 1,
+                num_slices: 1,
+                slice_len: vec![payload.len() as u32],
+                space_hash32: 2451163210,
+                modality: Modality::Text,
+                fragment_idx: 0,
+                fragment_total: 1,
+            },
+            slices: vec![(
+                SliceMeta {
+                    dtype: DType::F16,
+                    shape: vec![1, 4],
+                },
+                payload.clone(),
+            )],
+            crc32: 0,
+        };
+
+        let encoded = frame.encode_with_threshold(4096).unwrap();
+        let decoded = VFrame::decode(&encoded).unwrap();
+        assert_eq!(decoded.slices[0].1, payload);
+        // Below threshold the slice is carried raw: [compressed=0][orig_len=8][8 raw bytes].
+        assert_eq!(decoded.hdr.slice_len, vec![1 + 4 + 8]);
+    }
+
+    #[test]
+    fn test_xchacha_flag_seals_slice_region_and_roundtrips() {
+        let key = [9u8; 32];
+        let frame = VFrame {
+            hdr: VFrameHeader {
+                network: Network::Main,
+                version: 1,
+                mtype: MsgType::Think,
+                flags: Flags::XCHACHA,
+                stream_id: 0x1234,
+                frame_seq: 7,
+                num_slices: 1,
+                slice_len: vec![8],
+                space_hash32: 2451163210,
+                modality: Modality::Text,
+                fragment_idx: 0,
+                fragment_total: 1,
+            },
+            slices: vec![(
+                SliceMeta {
+                    dtype: DType::F16,
+                    shape: vec![1, 4],
+                },
+                vec![0xABu8; 8],
+            )],
+            crc32: 0,
+        };
+
+        let encoded = frame
+            .encode_encrypted(DEFAULT_COMPRESSION_THRESHOLD, &key)
+            .unwrap();
+        assert!(
+            VFrame::decode(&encoded).is_err(),
+            "plain decode must reject an XCHACHA-flagged frame"
+        );
+
+        let decoded = VFrame::decode_encrypted(&encoded, &key).unwrap();
+        assert_eq!(decoded.hdr.frame_seq, 7);
+        assert_eq!(decoded.slices[0].1, vec![0xABu8; 8]);
+    }
+
+    #[test]
+    fn test_xchacha_decode_rejects_tampered_ciphertext() {
+        let key = [9u8; 32];
+        let frame = VFrame {
+            hdr: VFrameHeader {
+                network: Network::Main,
+                version: 1,
+                mtype: MsgType::Think,
+                flags: Flags::XCHACHA,
+                stream_id: 0x1234,
+                frame_seq: 1,
+                num_slices: 1,
+                slice_len: vec![8],
+                space_hash32: 2451163210,
+                modality: Modality::Text,
+                fragment_idx: 0,
+                fragment_total: 1,
+            },
+            slices: vec![(
+                SliceMeta {
+                    dtype: DType::F16,
+                    shape: vec![1, 4],
+                },
+                vec![0u8; 8],
+            )],
+            crc32: 0,
+        };
+
+        let mut encoded = frame
+            .encode_encrypted(DEFAULT_COMPRESSION_THRESHOLD, &key)
+            .unwrap();
+        // Flip a byte inside the sealed slice region, well clear of the CRC32 trailer,
+        // then patch the trailer's CRC32 to match — CRC32 isn't a MAC, so an attacker
+        // can trivially recompute it after tampering. This exercises the AEAD tag
+        // actually catching the tamper once the (now-valid) CRC32 check passes.
+        let flip_at = encoded.len() - 8;
+        encoded[flip_at] ^= 0xFF;
+        let crc_len = encoded.len();
+        let recomputed_crc = crc32fast::hash(&encoded[..crc_len - 4]);
+        encoded[crc_len - 4..].copy_from_slice(&recomputed_crc.to_le_bytes());
+
+        let err = VFrame::decode_encrypted(&encoded, &key).unwrap_err();
+        assert!(err.downcast_ref::<crypto::FrameAuthError>().is_some());
+    }
+
+    #[test]
+    fn test_xchacha_decode_rejects_crc_mismatch_before_decrypting() {
+        let key = [9u8; 32];
+        let frame = VFrame {
+            hdr: VFrameHeader {
+                network: Network::Main,
+                version: 1,
+                mtype: MsgType::Think,
+                flags: Flags::XCHACHA,
+                stream_id: 0x1234,
+                frame_seq: 1,
+                num_slices: 1,
+                slice_len: vec![8],
+                space_hash32: 2451163210,
+                modality: Modality::Text,
+                fragment_idx: 0,
+                fragment_total: 1,
+            },
+            slices: vec![(
+                SliceMeta {
+                    dtype: DType::F16,
+                    shape: vec![1, 4],
+                },
+                vec![0u8; 8],
+            )],
+            crc32: 0,
+        };
+
+        let mut encoded = frame
+            .encode_encrypted(DEFAULT_COMPRESSION_THRESHOLD, &key)
+            .unwrap();
+        // Flip a byte without patching the CRC32 trailer: the cheap integrity check
+        // must reject this before an AEAD open is ever attempted.
+        let flip_at = encoded.len() - 8;
+        encoded[flip_at] ^= 0xFF;
+
+        let err = VFrame::decode_encrypted(&encoded, &key).unwrap_err();
+        assert!(err.downcast_ref::<crypto::FrameAuthError>().is_none());
+        assert!(err.to_string().contains("CRC32 mismatch"));
+    }
+
+    fn sample_header(stream_id: u32, frame_seq: u64) -> VFrameHeader {
+        VFrameHeader {
+            network: Network::Main,
+            version: 1,
+            mtype: MsgType::Think,
+            flags: Flags::empty(),
+            stream_id,
+            frame_seq,
+            num_slices: 1,
+            slice_len: vec![4],
+            space_hash32: 2451163210,
+            modality: Modality::Text,
+            fragment_idx: 0,
+            fragment_total: 1,
+        }
+    }
+
+    #[test]
+    fn test_decode_rejects_foreign_magic() {
+        let frame = VFrame {
+            hdr: sample_header(0x1234, 1),
+            slices: vec![(
+                SliceMeta {
+                    dtype: DType::I8,
+                    shape: vec![4],
+                },
+                vec![1, 2, 3, 4],
+            )],
+            crc32: 0,
+        };
+
+        let mut encoded = frame.encode().unwrap();
+        encoded[..4].copy_from_slice(b"XXXX");
+
+        let err = VFrame::decode(&encoded).unwrap_err();
+        let magic_err = err
+            .downcast_ref::<ForeignMagicError>()
+            .expect("expected ForeignMagicError");
+        assert_eq!(&magic_err.found, b"XXXX");
+    }
+
+    #[test]
+    fn test_find_next_locates_next_magic_offset() {
+        let frame = VFrame {
+            hdr: sample_header(0x1234, 1),
+            slices: vec![(
+                SliceMeta {
+                    dtype: DType::I8,
+                    shape: vec![4],
+                },
+                vec![1, 2, 3, 4],
+            )],
+            crc32: 0,
+        };
+        let encoded = frame.encode().unwrap();
+
+        let mut garbled = vec![0xAAu8; 7];
+        garbled.extend_from_slice(&encoded);
+
+        assert_eq!(VFrame::find_next(&garbled), Some(7));
+        assert_eq!(VFrame::find_next(&[0xAAu8; 3]), None);
+    }
+
+    fn test_capability(supported_protocols: Vec<u32>, compress: Vec<&str>, crypto: Vec<&str>) -> Capability {
+        Capability {
+            method: "capability".to_string(),
+            v: 1,
+            agreed_proto: 0,
+            d_model: 4096,
+            embedding_space_id: "universal-llm-v3".to_string(),
+            space_hash32: 2451163210,
+            compress: compress.into_iter().map(String::from).collect(),
+            crypto: crypto.into_iter().map(String::from).collect(),
+            supports: serde_json::json!({}),
+            x25519_pub: None,
+            compression_threshold: DEFAULT_COMPRESSION_THRESHOLD,
+            supported_protocols,
+        }
+    }
+
+    #[test]
+    fn test_negotiate_picks_highest_shared_protocol_version() {
+        let ours = test_capability(vec![1, 2], vec!["zstd"], vec!["xchacha20poly1305"]);
+        let peer = test_capability(vec![1, 2, 3], vec!["zstd"], vec!["xchacha20poly1305"]);
+        assert_eq!(ours.negotiate(&peer).unwrap(), 2);
+    }
+
+    #[test]
+    fn test_negotiate_rejects_no_shared_protocol_version() {
+        let ours = test_capability(vec![1], vec!["zstd"], vec!["xchacha20poly1305"]);
+        let peer = test_capability(vec![2], vec!["zstd"], vec!["xchacha20poly1305"]);
+        assert!(ours.negotiate(&peer).is_err());
+    }
+
+    #[test]
+    fn test_negotiate_compress_and_crypto_pick_first_shared_algorithm() {
+        let ours = test_capability(
+            vec![1],
+            vec!["zstd", "gzip"],
+            vec!["xchacha20poly1305", "aes-gcm"],
+        );
+        let peer = test_capability(vec![1], vec!["gzip"], vec!["aes-gcm"]);
+        assert_eq!(ours.negotiate_compress(&peer).unwrap(), "gzip");
+        assert_eq!(ours.negotiate_crypto(&peer).unwrap(), "aes-gcm");
+    }
 }
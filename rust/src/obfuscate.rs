@@ -0,0 +1,167 @@
+//! DPI-resistant obfuscation for the V-Stream wire format.
+//!
+//! A V-Frame's header layout is fixed and highly recognizable (constant `version`,
+//! a small set of `mtype` bytes, a constant `space_hash32` per deployment), which makes
+//! the protocol easy to fingerprint. When `Flags::OBFUSCATE` is set on a stream, two
+//! things change after the X25519 handshake: (1) random-length [`MsgType::Heart`]
+//! frames are interleaved with real traffic as cover, and (2) outgoing datagrams are
+//! padded up to a randomized bucket length so their size doesn't correlate with the
+//! real payload. A receiver strips that padding with [`strip_datagram_padding`] before
+//! handing the datagram to `VFrame::decode`.
+
+use anyhow::anyhow;
+use rand::Rng;
+
+use crate::{DType, Flags, Modality, MsgType, Network, SliceMeta, VFrame, VFrameHeader};
+
+/// Tunables for the obfuscation layer, letting an operator in a hostile network trade
+/// bandwidth and latency for unobservability.
+#[derive(Clone, Debug)]
+pub struct ObfuscationConfig {
+    /// Minimum size (bytes) of an injected Heart padding frame's payload.
+    pub min_padding_bytes: usize,
+    /// Maximum size (bytes) of an injected Heart padding frame's payload.
+    pub max_padding_bytes: usize,
+    /// Outgoing datagrams are padded up to the next multiple of this size.
+    pub bucket_size: usize,
+    /// Minimum inter-frame delay, to jitter the traffic's timing signature.
+    pub min_jitter_ms: u64,
+    /// Maximum inter-frame delay.
+    pub max_jitter_ms: u64,
+}
+
+impl Default for ObfuscationConfig {
+    fn default() -> Self {
+        Self {
+            min_padding_bytes: 16,
+            max_padding_bytes: 512,
+            bucket_size: 256,
+            min_jitter_ms: 0,
+            max_jitter_ms: 50,
+        }
+    }
+}
+
+/// Build a `Heart` keepalive frame filled with `len` bytes of random padding, so on the
+/// wire it's indistinguishable in size from a real `Think`/`Cache` frame. `cfg` controls
+/// the size range, so an operator's bandwidth/unobservability tradeoff actually reaches
+/// the cover traffic it's meant to shape.
+pub fn padding_frame(stream_id: u32, frame_seq: u64, cfg: &ObfuscationConfig) -> VFrame {
+    let mut rng = rand::thread_rng();
+    let len = rng.gen_range(cfg.min_padding_bytes..=cfg.max_padding_bytes);
+    let payload: Vec<u8> = (0..len).map(|_| rng.gen()).collect();
+
+    VFrame {
+        hdr: VFrameHeader {
+            network: Network::Main,
+            version: 1,
+            mtype: MsgType::Heart,
+            flags: Flags::OBFUSCATE,
+            stream_id,
+            frame_seq,
+            num_slices: 1,
+            slice_len: vec![len as u32],
+            space_hash32: 0,
+            modality: Modality::Text,
+            fragment_idx: 0,
+            fragment_total: 1,
+        },
+        slices: vec![(
+            SliceMeta {
+                dtype: DType::I8,
+                shape: vec![len as u32],
+            },
+            payload,
+        )],
+        crc32: 0,
+    }
+}
+
+/// Pad an encoded datagram up to the next multiple of `cfg.bucket_size` with random
+/// bytes, so the on-wire size of a real frame doesn't leak its true payload length.
+pub fn pad_datagram(datagram: &mut Vec<u8>, cfg: &ObfuscationConfig) {
+    let bucket = cfg.bucket_size.max(1);
+    let target = datagram.len().div_ceil(bucket) * bucket;
+    let pad_len = target - datagram.len();
+
+    let mut rng = rand::thread_rng();
+    datagram.extend((0..pad_len).map(|_| rng.gen::<u8>()));
+}
+
+/// Strip the random bucket padding [`pad_datagram`] appended, so the real V-Frame
+/// underneath can be decoded. Reuses `codec::peek_frame_len`'s on-wire length
+/// computation (header + slice table + declared slice lengths + CRC32 trailer) to find
+/// where the real frame ends, since the padding itself carries no length marker.
+pub fn strip_datagram_padding(datagram: &[u8]) -> anyhow::Result<&[u8]> {
+    let total_len = crate::codec::peek_frame_len(datagram)?
+        .ok_or_else(|| anyhow!("datagram is shorter than a full V-Frame"))?;
+    Ok(&datagram[..total_len])
+}
+
+/// Sample a random inter-frame delay within `cfg`'s jitter bounds.
+pub fn jitter_delay(cfg: &ObfuscationConfig) -> std::time::Duration {
+    let hi = cfg.max_jitter_ms.max(cfg.min_jitter_ms);
+    let ms = rand::thread_rng().gen_range(cfg.min_jitter_ms..=hi);
+    std::time::Duration::from_millis(ms)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_padding_frame_is_valid_heart_frame() {
+        let frame = padding_frame(0x1234, 7, &ObfuscationConfig::default());
+        let encoded = frame.encode().unwrap();
+        let decoded = VFrame::decode(&encoded).unwrap();
+
+        assert_eq!(decoded.hdr.mtype, MsgType::Heart);
+        assert!(decoded.hdr.flags.contains(Flags::OBFUSCATE));
+    }
+
+    #[test]
+    fn test_padding_frame_respects_configured_size_bounds() {
+        let cfg = ObfuscationConfig {
+            min_padding_bytes: 4,
+            max_padding_bytes: 4,
+            ..ObfuscationConfig::default()
+        };
+        let frame = padding_frame(0x1234, 7, &cfg);
+        assert_eq!(frame.slices[0].1.len(), 4);
+    }
+
+    #[test]
+    fn test_pad_datagram_rounds_up_to_bucket() {
+        let cfg = ObfuscationConfig {
+            bucket_size: 64,
+            ..ObfuscationConfig::default()
+        };
+        let mut datagram = vec![0u8; 10];
+        pad_datagram(&mut datagram, &cfg);
+        assert_eq!(datagram.len(), 64);
+
+        let mut exact = vec![0u8; 64];
+        pad_datagram(&mut exact, &cfg);
+        assert_eq!(exact.len(), 64);
+    }
+
+    #[test]
+    fn test_strip_datagram_padding_recovers_the_real_frame() {
+        let frame = padding_frame(0x1234, 7, &ObfuscationConfig::default());
+        let mut datagram = frame.encode().unwrap();
+        let real_len = datagram.len();
+
+        let cfg = ObfuscationConfig {
+            bucket_size: real_len + 512,
+            ..ObfuscationConfig::default()
+        };
+        pad_datagram(&mut datagram, &cfg);
+        assert!(datagram.len() > real_len);
+
+        let stripped = strip_datagram_padding(&datagram).unwrap();
+        assert_eq!(stripped.len(), real_len);
+
+        let decoded = VFrame::decode(stripped).unwrap();
+        assert_eq!(decoded.hdr.mtype, MsgType::Heart);
+    }
+}
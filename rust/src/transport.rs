@@ -0,0 +1,147 @@
+use anyhow::{ensure, Context};
+use async_trait::async_trait;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpStream, UnixStream};
+
+use crate::VFrame;
+
+/// Hard cap on a length-prefixed frame's declared size, mirroring the 64 KiB V-Frame
+/// convention used elsewhere (the UDP examples' receive buffer, `crypto::STREAM_CHUNK_SIZE`).
+/// `read_length_prefixed` rejects a prefix above this before allocating, so a peer can't
+/// force a multi-GB allocation with a single 4-byte length lie.
+pub const MAX_FRAME_LEN: u32 = 64 * 1024;
+
+/// Carries V-Frames over a reliable byte stream (TCP, Unix-domain socket, a WebRTC
+/// data channel, ...), as opposed to a datagram transport like UDP where each
+/// `recv_from` already lines up with one frame.
+///
+/// Byte streams have no datagram boundaries, so `read_frame`/`write_frame` wrap each
+/// frame in a 4-byte little-endian length prefix (see [`read_length_prefixed`]) and
+/// `VFrame::encode`/`decode` run unchanged on the prefixed body.
+#[async_trait]
+pub trait AsyncProtocolStream: Send {
+    async fn read_frame(&mut self) -> anyhow::Result<VFrame>;
+    async fn write_frame(&mut self, frame: &VFrame) -> anyhow::Result<()>;
+}
+
+#[async_trait]
+impl<T> AsyncProtocolStream for T
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    async fn read_frame(&mut self) -> anyhow::Result<VFrame> {
+        let bytes = read_length_prefixed(self).await?;
+        VFrame::decode(&bytes)
+    }
+
+    async fn write_frame(&mut self, frame: &VFrame) -> anyhow::Result<()> {
+        let bytes = frame.encode()?;
+        write_length_prefixed(self, &bytes).await
+    }
+}
+
+/// TCP transport. A plain type alias is enough since [`AsyncProtocolStream`] is
+/// implemented generically for anything that reads and writes bytes.
+pub type TcpProtocolStream = TcpStream;
+
+/// Unix-domain socket transport, for peers sharing a host (e.g. a sidecar process).
+pub type UnixProtocolStream = UnixStream;
+
+/// An in-memory connected pair, for exercising the V-Frame path in tests without a
+/// real socket. Each half implements [`AsyncProtocolStream`] like any other stream.
+pub fn loopback_pair(buf_size: usize) -> (tokio::io::DuplexStream, tokio::io::DuplexStream) {
+    tokio::io::duplex(buf_size)
+}
+
+async fn read_length_prefixed<R: AsyncRead + Unpin + Send + ?Sized>(
+    stream: &mut R,
+) -> anyhow::Result<Vec<u8>> {
+    let len = stream
+        .read_u32_le()
+        .await
+        .context("Failed to read frame length prefix")?;
+    ensure!(
+        len <= MAX_FRAME_LEN,
+        "frame length prefix {} exceeds MAX_FRAME_LEN ({})",
+        len,
+        MAX_FRAME_LEN
+    );
+    let mut buf = vec![0u8; len as usize];
+    stream
+        .read_exact(&mut buf)
+        .await
+        .context("Failed to read length-prefixed frame body")?;
+    Ok(buf)
+}
+
+async fn write_length_prefixed<W: AsyncWrite + Unpin + Send + ?Sized>(
+    stream: &mut W,
+    bytes: &[u8],
+) -> anyhow::Result<()> {
+    stream
+        .write_u32_le(bytes.len() as u32)
+        .await
+        .context("Failed to write frame length prefix")?;
+    stream
+        .write_all(bytes)
+        .await
+        .context("Failed to write frame body")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DType, Flags, Modality, MsgType, Network, SliceMeta, VFrameHeader};
+
+    fn sample_frame() -> VFrame {
+        VFrame {
+            hdr: VFrameHeader {
+                network: Network::Main,
+                version: 1,
+                mtype: MsgType::Think,
+                flags: Flags::empty(),
+                stream_id: 0x1234,
+                frame_seq: 1,
+                num_slices: 1,
+                slice_len: vec![4],
+                space_hash32: 2451163210,
+                modality: Modality::Text,
+                fragment_idx: 0,
+                fragment_total: 1,
+            },
+            slices: vec![(
+                SliceMeta {
+                    dtype: DType::I8,
+                    shape: vec![4],
+                },
+                vec![1, 2, 3, 4],
+            )],
+            crc32: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_loopback_roundtrip() {
+        let (mut a, mut b) = loopback_pair(4096);
+        let frame = sample_frame();
+
+        a.write_frame(&frame).await.unwrap();
+        let received = b.read_frame().await.unwrap();
+
+        assert_eq!(received.hdr.stream_id, frame.hdr.stream_id);
+        assert_eq!(received.slices[0].1, frame.slices[0].1);
+    }
+
+    #[tokio::test]
+    async fn test_read_frame_rejects_oversized_length_prefix() {
+        let (mut a, mut b) = loopback_pair(4096);
+
+        a.write_all(&(MAX_FRAME_LEN + 1).to_le_bytes())
+            .await
+            .unwrap();
+
+        let err = b.read_frame().await.unwrap_err();
+        assert!(err.to_string().contains("exceeds MAX_FRAME_LEN"));
+    }
+}
@@ -0,0 +1,269 @@
+//! Session-level state for a V-Stream: deterministic nonce derivation and anti-replay.
+//!
+//! `crypto::open_xchacha` accepts an arbitrary caller-supplied nonce, which makes nonce
+//! reuse (catastrophic for XChaCha20-Poly1305) an easy mistake, and nothing checks that
+//! a `frame_seq` hasn't already been seen. [`VStreamSession`] fixes both: it derives
+//! each frame's nonce deterministically from `(stream_id, frame_seq)` and tracks a
+//! sliding replay window on the receive side.
+
+use anyhow::ensure;
+
+use crate::VFrame;
+
+/// Width of the anti-replay window, in frame sequence numbers behind the high-water
+/// mark. Mirrors the window size used by IPsec/ESP anti-replay.
+const REPLAY_WINDOW_SIZE: u64 = 64;
+
+/// A `frame_seq` rejected by [`ReplayWindow::check_and_record`] — either seen before or
+/// too far behind the window to tell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplayError {
+    /// `frame_seq` has already been accepted for this stream.
+    AlreadySeen { frame_seq: u64 },
+    /// `frame_seq` falls further behind the high-water mark than the window covers.
+    TooOld { frame_seq: u64, high_water_mark: u64 },
+}
+
+impl std::fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReplayError::AlreadySeen { frame_seq } => {
+                write!(f, "frame_seq {frame_seq} is a replay (already accepted)")
+            }
+            ReplayError::TooOld {
+                frame_seq,
+                high_water_mark,
+            } => write!(
+                f,
+                "frame_seq {frame_seq} is too old (high water mark is {high_water_mark}, window is {REPLAY_WINDOW_SIZE})"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+/// Sliding replay-protection window over `frame_seq` values for one stream: a 64-bit
+/// bitmap of recently-accepted sequence numbers plus a high-water mark. Out-of-order
+/// delivery within the window is allowed; duplicates and far-past sequences are not.
+pub struct ReplayWindow {
+    high_water_mark: u64,
+    seen: u64,
+    initialized: bool,
+}
+
+impl ReplayWindow {
+    pub fn new() -> Self {
+        Self {
+            high_water_mark: 0,
+            seen: 0,
+            initialized: false,
+        }
+    }
+
+    /// Check `frame_seq` against the window, recording it as seen if accepted.
+    pub fn check_and_record(&mut self, frame_seq: u64) -> Result<(), ReplayError> {
+        if !self.initialized {
+            self.initialized = true;
+            self.high_water_mark = frame_seq;
+            self.seen = 1;
+            return Ok(());
+        }
+
+        if frame_seq > self.high_water_mark {
+            let shift = frame_seq - self.high_water_mark;
+            self.seen = if shift >= REPLAY_WINDOW_SIZE {
+                0
+            } else {
+                self.seen << shift
+            };
+            self.seen |= 1;
+            self.high_water_mark = frame_seq;
+            return Ok(());
+        }
+
+        let behind = self.high_water_mark - frame_seq;
+        if behind >= REPLAY_WINDOW_SIZE {
+            return Err(ReplayError::TooOld {
+                frame_seq,
+                high_water_mark: self.high_water_mark,
+            });
+        }
+
+        let bit = 1u64 << behind;
+        if self.seen & bit != 0 {
+            return Err(ReplayError::AlreadySeen { frame_seq });
+        }
+        self.seen |= bit;
+        Ok(())
+    }
+}
+
+impl Default for ReplayWindow {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Derive a frame's nonce deterministically from `(stream_id, frame_seq)`: `stream_id`
+/// (4B) || `frame_seq` (8B), zero-padded to the 24 bytes XChaCha20-Poly1305 needs. As
+/// long as a sender never reuses `frame_seq` on a given `stream_id`, the nonce never
+/// repeats, removing the need for callers to manage nonces themselves.
+pub fn derive_frame_nonce(stream_id: u32, frame_seq: u64) -> [u8; 24] {
+    let mut nonce = [0u8; 24];
+    nonce[0..4].copy_from_slice(&stream_id.to_le_bytes());
+    nonce[4..12].copy_from_slice(&frame_seq.to_le_bytes());
+    nonce
+}
+
+/// Receive-side state for one V-Stream: owns nonce derivation and enforces the
+/// anti-replay window over decoded frames.
+pub struct VStreamSession {
+    stream_id: u32,
+    replay: ReplayWindow,
+}
+
+impl VStreamSession {
+    pub fn new(stream_id: u32) -> Self {
+        Self {
+            stream_id,
+            replay: ReplayWindow::new(),
+        }
+    }
+
+    /// The nonce this stream must use to seal/open `frame_seq`.
+    pub fn nonce_for(&self, frame_seq: u64) -> [u8; 24] {
+        derive_frame_nonce(self.stream_id, frame_seq)
+    }
+
+    /// Decode a frame belonging to this session's stream and enforce the anti-replay
+    /// window, rejecting duplicates and far-past sequences rather than silently
+    /// accepting a replayed frame.
+    pub fn decode_frame(&mut self, data: &[u8]) -> anyhow::Result<VFrame> {
+        let frame = VFrame::decode(data)?;
+        self.check_stream_and_replay(&frame)?;
+        Ok(frame)
+    }
+
+    /// Decode an XCHACHA-sealed frame belonging to this session's stream via
+    /// [`VFrame::decode_encrypted`] and enforce the same anti-replay window as
+    /// [`VStreamSession::decode_frame`]. Without this, a captured, validly-encrypted
+    /// frame could be replayed indefinitely — the AEAD tag alone proves authenticity,
+    /// not freshness.
+    pub fn decode_encrypted_frame(&mut self, data: &[u8], key: &[u8; 32]) -> anyhow::Result<VFrame> {
+        let frame = VFrame::decode_encrypted(data, key)?;
+        self.check_stream_and_replay(&frame)?;
+        Ok(frame)
+    }
+
+    fn check_stream_and_replay(&mut self, frame: &VFrame) -> anyhow::Result<()> {
+        ensure!(
+            frame.hdr.stream_id == self.stream_id,
+            "frame belongs to stream {:#x}, session is for {:#x}",
+            frame.hdr.stream_id,
+            self.stream_id
+        );
+        self.replay
+            .check_and_record(frame.hdr.frame_seq)
+            .map_err(anyhow::Error::new)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        DType, Flags, Modality, MsgType, Network, SliceMeta, VFrameHeader,
+        DEFAULT_COMPRESSION_THRESHOLD,
+    };
+
+    #[test]
+    fn test_replay_window_accepts_increasing_and_reordered_sequences() {
+        let mut window = ReplayWindow::new();
+        assert!(window.check_and_record(10).is_ok());
+        assert!(window.check_and_record(12).is_ok());
+        assert!(window.check_and_record(11).is_ok()); // reordered, still within window
+    }
+
+    #[test]
+    fn test_replay_window_rejects_duplicate() {
+        let mut window = ReplayWindow::new();
+        window.check_and_record(5).unwrap();
+        let err = window.check_and_record(5).unwrap_err();
+        assert_eq!(err, ReplayError::AlreadySeen { frame_seq: 5 });
+    }
+
+    #[test]
+    fn test_replay_window_rejects_far_past_sequence() {
+        let mut window = ReplayWindow::new();
+        window.check_and_record(1000).unwrap();
+        let err = window.check_and_record(900).unwrap_err();
+        assert_eq!(
+            err,
+            ReplayError::TooOld {
+                frame_seq: 900,
+                high_water_mark: 1000
+            }
+        );
+    }
+
+    #[test]
+    fn test_derive_frame_nonce_varies_with_stream_and_seq() {
+        let a = derive_frame_nonce(1, 1);
+        let b = derive_frame_nonce(1, 2);
+        let c = derive_frame_nonce(2, 1);
+        assert_ne!(a, b);
+        assert_ne!(a, c);
+    }
+
+    fn encrypted_frame(stream_id: u32, frame_seq: u64, key: &[u8; 32]) -> Vec<u8> {
+        let frame = VFrame {
+            hdr: VFrameHeader {
+                network: Network::Main,
+                version: 1,
+                mtype: MsgType::Think,
+                flags: Flags::XCHACHA,
+                stream_id,
+                frame_seq,
+                num_slices: 1,
+                slice_len: vec![4],
+                space_hash32: 2451163210,
+                modality: Modality::Text,
+                fragment_idx: 0,
+                fragment_total: 1,
+            },
+            slices: vec![(
+                SliceMeta {
+                    dtype: DType::I8,
+                    shape: vec![4],
+                },
+                vec![1, 2, 3, 4],
+            )],
+            crc32: 0,
+        };
+        frame
+            .encode_encrypted(DEFAULT_COMPRESSION_THRESHOLD, key)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_decode_encrypted_frame_accepts_first_delivery() {
+        let key = [4u8; 32];
+        let mut session = VStreamSession::new(0x1234);
+        let data = encrypted_frame(0x1234, 1, &key);
+
+        let frame = session.decode_encrypted_frame(&data, &key).unwrap();
+        assert_eq!(frame.hdr.frame_seq, 1);
+    }
+
+    #[test]
+    fn test_decode_encrypted_frame_rejects_replay() {
+        let key = [4u8; 32];
+        let mut session = VStreamSession::new(0x1234);
+        let data = encrypted_frame(0x1234, 1, &key);
+
+        session.decode_encrypted_frame(&data, &key).unwrap();
+        let err = session.decode_encrypted_frame(&data, &key).unwrap_err();
+        assert!(err.downcast_ref::<ReplayError>().is_some());
+    }
+}